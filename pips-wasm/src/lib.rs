@@ -3,10 +3,16 @@ extern crate serde_derive;
 
 extern crate pips;
 
+use pips::expression::{QueryError, UnmetCondition};
 use pips::parse;
+use pips::random::{seeded_rng, STREAM_VERSION};
+use pips::statistics::Statistics;
+use pips::trace::Trace;
+use pips::traits::Chance;
 use pips::traits::PlotResult;
 use pips::traits::RollResult;
 use pips::traits::Rollable;
+use pips::ParseError;
 
 use wasm_bindgen::prelude::*;
 
@@ -19,16 +25,108 @@ export interface Ok<T> { type: "Ok", value: T }
 export interface Err<T> { type: "Err", value: T }
 export type Result<T, E> = Ok<T> | Err<E>;
 
+export interface ParseError {
+    /** position of the error in the input, in bytes */
+    offset: number;
+    /** line of the error, starting at 1 */
+    line: number;
+    /** column of the error in characters, starting at 1 */
+    column: number;
+    /** what could have come next instead, if anything */
+    expected: string[];
+    /** a description of the error for people */
+    message: string;
+}
+
+/** an expression that parsed, but couldn't be evaluated */
+export interface EvaluationError {
+    /** `UnmetCondition` when no roll met every condition, or `NotAQuery` */
+    kind: "UnmetCondition" | "NotAQuery";
+    /** a description of the error for people */
+    message: string;
+}
+
+/** an error from a function that evaluates an expression after parsing it */
+export type PipsError = ({ kind: "Parse" } & ParseError) | EvaluationError;
+
+export interface Distribution {
+    /** the lowest possible outcome */
+    offset: number;
+    /** likelihood of each outcome in order, starting at `offset` */
+    chances: number[];
+}
+
 export interface PlotResult {
     total: number;
-    plot: Record<number, number>;
+    plot: Distribution;
 }
 
-/** parse and roll a dice expression */
-export function roll(input: string): Result<number, string>;
+/**
+ * parse and roll a dice expression. rolls with the same seed are the same on
+ * every platform. conditions that no roll meets are reported as an
+ * `UnmetCondition` error.
+ */
+export function roll(input: string, seed?: bigint): Result<number, PipsError>;
+
+export interface Trace {
+    /** the part of the expression that was rolled, e.g. `4d6` */
+    expression: string;
+    /** every die rolled directly by this part of the expression */
+    dice: number[];
+    value: number;
+    /** whether this outcome was thrown away, like the lower roll of `adv` */
+    dropped: boolean;
+    children: Trace[];
+}
+
+/** the version of the stream of seeded rolls, which changes whenever a seed would roll differently */
+export function stream_version(): number;
+
+/** parse and roll a dice expression, keeping every die that was rolled. fails like `roll`. */
+export function roll_detailed(input: string, seed?: bigint): Result<Trace, PipsError>;
+
+export interface Statistics {
+    mean: number;
+    variance: number;
+    standard_deviation: number;
+    median: number | null;
+    modes: number[];
+    skewness: number;
+    excess_kurtosis: number;
+}
 
 /** parse and plot a dice expression */
-export function plot(input: string): Result<PlotResult, string>;
+export function plot(input: string): Result<PlotResult, ParseError>;
+
+/**
+ * parse and answer a query, such as `P(2d6 >= 8)`, `E[2d6 + 3]` or `Var[2d6]`.
+ * queries about expressions whose conditions are never met fail with
+ * `UnmetCondition`.
+ */
+export function query(input: string): Result<number, PipsError>;
+
+/** parse a dice expression and summarize its plot */
+export function statistics(input: string): Result<Statistics, ParseError>;
+
+export interface PlotSummary {
+    plot: PlotResult;
+    statistics: Statistics;
+}
+
+/** parse and plot a dice expression once, along with the summary of its plot */
+export function plot_summary(input: string): Result<PlotSummary, ParseError>;
+
+/** parse a dice expression and find the chance of rolling each outcome or lower */
+export function cumulative(input: string): Result<Array<[number, number]>, ParseError>;
+
+/** parse a dice expression and find the chance of rolling each outcome or higher */
+export function survival(input: string): Result<Array<[number, number]>, ParseError>;
+
+/**
+ * parse a dice expression and find the chance of rolling between `min` and
+ * `max`, inclusive. either bound may be left out.
+ */
+export function chance_between(input: string, min?: number, max?: number): Result<number, ParseError>;
 
 "#;
 
@@ -39,26 +137,189 @@ pub enum PipsResult<R, E> {
     Err(E),
 }
 
+/// An error from a function that evaluates an expression after parsing it,
+/// tagged with its kind so that errors in the input can be told apart
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum PipsError {
+    Parse(ParseError),
+    UnmetCondition { message: String },
+    NotAQuery { message: String },
+}
+
+impl From<ParseError> for PipsError {
+    fn from(err: ParseError) -> PipsError {
+        PipsError::Parse(err)
+    }
+}
+
+impl From<UnmetCondition> for PipsError {
+    fn from(err: UnmetCondition) -> PipsError {
+        PipsError::UnmetCondition {
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<QueryError> for PipsError {
+    fn from(err: QueryError) -> PipsError {
+        match err {
+            QueryError::NotAQuery => PipsError::NotAQuery {
+                message: err.to_string(),
+            },
+            QueryError::NoOutcomes => PipsError::UnmetCondition {
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
 #[wasm_bindgen]
-pub fn roll(input: &str) -> JsValue {
+pub fn roll(input: &str, seed: Option<u64>) -> JsValue {
     utils::set_panic_hook();
 
-    let result: PipsResult<RollResult, String> = match parse(input) {
-        Ok(expr) => PipsResult::Ok(expr.roll()),
-        Err(err) => PipsResult::Err(format!("{:?}", err)),
+    let result: PipsResult<RollResult, PipsError> = match parse(input) {
+        Ok(expr) => {
+            let roll = match seed {
+                Some(seed) => expr.try_roll_with(&mut seeded_rng(seed)),
+                None => expr.try_roll(),
+            };
+            match roll {
+                Ok(value) => PipsResult::Ok(value),
+                Err(err) => PipsResult::Err(err.into()),
+            }
+        }
+        Err(err) => PipsResult::Err(err.into()),
     };
 
-    JsValue::from_serde(&result).unwrap()
+    utils::to_js_value(&result)
+}
+
+#[wasm_bindgen]
+pub fn stream_version() -> u32 {
+    STREAM_VERSION
+}
+
+#[wasm_bindgen]
+pub fn roll_detailed(input: &str, seed: Option<u64>) -> JsValue {
+    utils::set_panic_hook();
+
+    let result: PipsResult<Trace, PipsError> = match parse(input) {
+        Ok(expr) => {
+            let trace = match seed {
+                Some(seed) => expr.roll_detailed_with(&mut seeded_rng(seed)),
+                None => expr.roll_detailed(),
+            };
+            match trace {
+                Ok(trace) => PipsResult::Ok(trace),
+                Err(err) => PipsResult::Err(err.into()),
+            }
+        }
+        Err(err) => PipsResult::Err(err.into()),
+    };
+
+    utils::to_js_value(&result)
 }
 
 #[wasm_bindgen]
 pub fn plot(input: &str) -> JsValue {
     utils::set_panic_hook();
 
-    let result: PipsResult<PlotResult, String> = match parse(input) {
+    let result: PipsResult<PlotResult, ParseError> = match parse(input) {
         Ok(expr) => PipsResult::Ok(expr.plot()),
-        Err(err) => PipsResult::Err(format!("{:?}", err)),
+        Err(err) => PipsResult::Err(err),
+    };
+
+    utils::to_js_value(&result)
+}
+
+#[wasm_bindgen]
+pub fn query(input: &str) -> JsValue {
+    utils::set_panic_hook();
+
+    let result: PipsResult<f64, PipsError> = match parse(input) {
+        Ok(expr) => match expr.evaluate() {
+            Ok(answer) => PipsResult::Ok(answer),
+            Err(err) => PipsResult::Err(err.into()),
+        },
+        Err(err) => PipsResult::Err(err.into()),
+    };
+
+    utils::to_js_value(&result)
+}
+
+#[wasm_bindgen]
+pub fn statistics(input: &str) -> JsValue {
+    utils::set_panic_hook();
+
+    let result: PipsResult<Statistics, ParseError> = match parse(input) {
+        Ok(expr) => PipsResult::Ok(expr.plot().plot.statistics()),
+        Err(err) => PipsResult::Err(err),
+    };
+
+    utils::to_js_value(&result)
+}
+
+/// A plot along with its summary statistics
+#[derive(Serialize, Deserialize)]
+pub struct PlotSummary {
+    plot: PlotResult,
+    statistics: Statistics,
+}
+
+#[wasm_bindgen]
+pub fn plot_summary(input: &str) -> JsValue {
+    utils::set_panic_hook();
+
+    let result: PipsResult<PlotSummary, ParseError> = match parse(input) {
+        Ok(expr) => {
+            let plot = expr.plot();
+            PipsResult::Ok(PlotSummary {
+                statistics: plot.plot.statistics(),
+                plot,
+            })
+        }
+        Err(err) => PipsResult::Err(err),
+    };
+
+    utils::to_js_value(&result)
+}
+
+#[wasm_bindgen]
+pub fn cumulative(input: &str) -> JsValue {
+    utils::set_panic_hook();
+
+    let result: PipsResult<Vec<(RollResult, Chance)>, ParseError> = match parse(input) {
+        Ok(expr) => PipsResult::Ok(expr.plot().plot.cumulative()),
+        Err(err) => PipsResult::Err(err),
+    };
+
+    utils::to_js_value(&result)
+}
+
+#[wasm_bindgen]
+pub fn survival(input: &str) -> JsValue {
+    utils::set_panic_hook();
+
+    let result: PipsResult<Vec<(RollResult, Chance)>, ParseError> = match parse(input) {
+        Ok(expr) => PipsResult::Ok(expr.plot().plot.survival()),
+        Err(err) => PipsResult::Err(err),
+    };
+
+    utils::to_js_value(&result)
+}
+
+#[wasm_bindgen]
+pub fn chance_between(input: &str, min: Option<RollResult>, max: Option<RollResult>) -> JsValue {
+    utils::set_panic_hook();
+
+    let result: PipsResult<Chance, ParseError> = match parse(input) {
+        Ok(expr) => PipsResult::Ok(expr.plot().plot.chance_between(
+            min.unwrap_or(RollResult::MIN),
+            max.unwrap_or(RollResult::MAX),
+        )),
+        Err(err) => PipsResult::Err(err),
     };
 
-    JsValue::from_serde(&result).unwrap()
+    utils::to_js_value(&result)
 }
@@ -8,3 +8,11 @@ pub fn set_panic_hook() {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
 }
+
+/// serialize a value into a `JsValue`
+// `JsValue::from_serde` is deprecated in newer versions of wasm-bindgen, but
+// the replacement crates would change the shape of the exported values.
+#[allow(deprecated)]
+pub fn to_js_value<T: serde::Serialize>(value: &T) -> wasm_bindgen::JsValue {
+    wasm_bindgen::JsValue::from_serde(value).unwrap()
+}
@@ -7,14 +7,16 @@ import {
     EventEmitter,
 } from '@angular/core';
 import * as Highcharts from 'highcharts';
-import { PlotResult } from 'pips-wasm';
+import { Distribution, Statistics } from 'pips-wasm';
 
 import { PipsService } from '../pips.service';
 import { Round } from '../../utilities/numbers';
+import { FormatParseError } from '../../utilities/errors';
 
 export interface ExpressionResult {
     expression: string;
-    values: PlotResult;
+    values: Distribution;
+    statistics: Statistics;
 }
 
 @Component({
@@ -40,15 +42,17 @@ export class ChartComponent implements OnChanges {
     }
 
     async plotExpression(expression: string): Promise<ExpressionResult> {
-        const result = await this._pipsService.plot(expression);
+        const result = await this._pipsService.plotSummary(expression);
 
         if (result.type !== 'Ok') {
-            this.errors = [...this.errors, result.value];
-            throw new Error(result.value);
+            const message = FormatParseError(result.value);
+            this.errors = [...this.errors, message];
+            throw new Error(message);
         }
 
         return {
-            values: result.value.plot,
+            values: result.value.plot.plot,
+            statistics: result.value.statistics,
             expression,
         };
     }
@@ -79,10 +83,12 @@ export class ChartComponent implements OnChanges {
         }
 
         const xValues = results
-            .map((result) => Object.keys(result.values))
-            .reduce((all, some) => all.concat(some), [])
-            .map(Number)
-            .filter((x) => !isNaN(x));
+            .filter((result) => result.values.chances.length > 0)
+            .map((result) => [
+                result.values.offset,
+                result.values.offset + result.values.chances.length - 1,
+            ])
+            .reduce((all, some) => all.concat(some), []);
 
         const xMin = Math.min(...xValues);
         const xMax = Math.max(...xValues);
@@ -90,11 +96,9 @@ export class ChartComponent implements OnChanges {
         const allData = results.map((result) => {
             return {
                 result,
-                points: Object.keys(result.values)
-                    .map(Number)
-                    .filter((x) => !isNaN(x))
-                    .sort((a, b) => a - b)
-                    .map<[number, number]>((x) => [x, result.values[x]]),
+                points: result.values.chances.map<[number, number]>(
+                    (chance, index) => [result.values.offset + index, chance],
+                ),
             };
         });
 
@@ -109,8 +113,8 @@ export class ChartComponent implements OnChanges {
         );
 
         const meanLines = allData.map(
-            ({ result, points }): Highcharts.XAxisPlotLinesOptions => {
-                const average = points.reduce((sum, [x, y]) => sum + x * y, 0);
+            ({ result }): Highcharts.XAxisPlotLinesOptions => {
+                const average = result.statistics.mean;
 
                 return {
                     dashStyle: 'Dash',
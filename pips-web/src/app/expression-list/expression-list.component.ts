@@ -13,6 +13,7 @@ import { fromEvent, Subject } from 'rxjs';
 import { takeUntil, filter } from 'rxjs/operators';
 
 import { PipsService } from '../pips.service';
+import { FormatError } from '../../utilities/errors';
 
 @Component({
     selector: 'app-expression-list',
@@ -63,7 +64,10 @@ export class ExpressionComponent implements OnInit, OnDestroy {
     async roll(expression: string) {
         this.result = '...';
         const result = await this._pipsService.roll(expression);
-        this.result = String(result.value);
+        this.result =
+            result.type === 'Ok'
+                ? String(result.value)
+                : FormatError(result.value);
     }
 
     addExpression(expression: string) {
@@ -6,13 +6,23 @@ const pips$ = import('pips-wasm');
     providedIn: 'root',
 })
 export class PipsService {
-    async roll(input: string) {
+    async roll(input: string, seed?: bigint) {
         const pips = await pips$;
-        return pips.roll(input);
+        return pips.roll(input, seed);
+    }
+
+    async rollDetailed(input: string, seed?: bigint) {
+        const pips = await pips$;
+        return pips.roll_detailed(input, seed);
     }
 
     async plot(input: string) {
         const pips = await pips$;
         return pips.plot(input);
     }
+
+    async plotSummary(input: string) {
+        const pips = await pips$;
+        return pips.plot_summary(input);
+    }
 }
@@ -0,0 +1,22 @@
+import { ParseError, PipsError } from 'pips-wasm';
+
+/**
+ * Describe a parse error with where it happened and what was expected there
+ * @param error an error from parsing an expression
+ */
+export function FormatParseError(error: ParseError): string {
+    const position = `${error.message} at line ${error.line}, column ${error.column}`;
+    if (error.expected.length === 0) {
+        return position;
+    }
+    return `${position}, expected ${error.expected.join(' or ')}`;
+}
+
+/**
+ * Describe an error from parsing or evaluating an expression. Only parse
+ * errors have a position in the input
+ * @param error an error from parsing or evaluating an expression
+ */
+export function FormatError(error: PipsError): string {
+    return error.kind === 'Parse' ? FormatParseError(error) : error.message;
+}
@@ -0,0 +1,178 @@
+//! Alias Module
+//!
+//! Sampling outcomes from a plot in constant time with Vose's alias method,
+//! without rolling the expression again. Every outcome gets a column: a
+//! column is picked at random, then a biased coin decides between the outcome
+//! of the column and its alias.
+
+use crate::distribution::Distribution;
+use crate::random::below;
+use crate::traits::{PlotResult, RollResult};
+use crate::weight::Weight;
+use rand::distributions;
+use rand::Rng;
+
+/// The biased coin of a column that always keeps its own outcome
+const CERTAIN: u64 = 1 << 32;
+
+/// A table for sampling outcomes from a distribution in constant time, which
+/// works with `rand` as a `rand::distributions::Distribution`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AliasTable {
+    /// the outcome of the first column
+    offset: RollResult,
+    /// the chance of keeping the outcome of each column rather than its
+    /// alias, out of 2^32
+    keep: Vec<u64>,
+    /// the column whose outcome is taken instead
+    alias: Vec<u32>,
+}
+
+impl AliasTable {
+    /// Create a table for sampling from a distribution
+    ///
+    /// panics if the distribution has no possible outcomes
+    pub fn new<W: Weight>(distribution: &Distribution<W>) -> AliasTable {
+        let chances = distribution.to_chances();
+        assert!(
+            !chances.is_empty(),
+            "can't sample from a distribution without outcomes"
+        );
+
+        let columns = chances.len();
+        let mass = chances.mass();
+        // scaled so that the average column is 1
+        let mut scaled: Vec<f64> = chances
+            .chances()
+            .iter()
+            .map(|chance| chance / mass * columns as f64)
+            .collect();
+        let mut keep = vec![CERTAIN; columns];
+        let mut alias: Vec<u32> = (0..columns as u32).collect();
+
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..columns).partition(|column| scaled[*column] < 1.0);
+
+        // fill every small column with the excess of a large one
+        while let (Some(&under), Some(&over)) = (small.last(), large.last()) {
+            small.pop();
+            keep[under] = (scaled[under] * CERTAIN as f64).round() as u64;
+            alias[under] = over as u32;
+
+            scaled[over] -= 1.0 - scaled[under];
+            if scaled[over] < 1.0 {
+                large.pop();
+                small.push(over);
+            }
+        }
+        // anything left over is full, up to floating point error
+
+        AliasTable {
+            offset: chances.min().expect("distribution has a possible outcome"),
+            keep,
+            alias,
+        }
+    }
+
+    /// Sample an outcome with the given random number generator
+    ///
+    /// this draws a number below the number of columns, then a single 32 bit
+    /// output for the coin, so samples are portable like seeded rolls
+    pub fn sample_with<R: Rng + ?Sized>(&self, rng: &mut R) -> RollResult {
+        let column = below(self.keep.len() as u32, rng) as usize;
+        let coin = u64::from(rng.next_u32());
+
+        let column = if coin < self.keep[column] {
+            column
+        } else {
+            self.alias[column] as usize
+        };
+        self.offset + column as RollResult
+    }
+}
+
+impl distributions::Distribution<RollResult> for AliasTable {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> RollResult {
+        self.sample_with(rng)
+    }
+}
+
+impl<W: Weight> Distribution<W> {
+    /// Create a table for sampling outcomes from this distribution in
+    /// constant time
+    pub fn sampler(&self) -> AliasTable {
+        AliasTable::new(self)
+    }
+}
+
+impl PlotResult {
+    /// Create a table for sampling outcomes from this plot in constant time
+    pub fn sampler(&self) -> AliasTable {
+        AliasTable::new(&self.plot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::seeded_rng;
+    use crate::traits::Rollable;
+    use crate::verification::goodness_of_fit;
+    use crate::weight::Exact;
+
+    #[test]
+    fn table_splits_columns() {
+        let distribution = Distribution::new(1, vec![0.5, 0.25, 0.25]);
+
+        let actual = distribution.sampler();
+
+        // the two small columns are topped up by the first
+        assert_eq!(actual.keep, vec![CERTAIN, 3 << 30, 3 << 30]);
+        assert_eq!(actual.alias, vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn impossible_outcomes_are_never_sampled() {
+        let distribution = Distribution::<Exact>::new(
+            -1,
+            vec![Exact::ratio(1, 2), Exact::zero(), Exact::ratio(1, 2)],
+        );
+        let table = distribution.sampler();
+        let mut rng = seeded_rng(5);
+
+        for _ in 0..1000 {
+            let sample = rng.sample(&table);
+            assert!(sample == -1 || sample == 1, "{}", sample);
+        }
+    }
+
+    #[test]
+    fn samples_fit_plot() {
+        let expression = crate::parse("adv(2d6) + d4 - 3").unwrap();
+        let plot = expression.plot();
+        let table = plot.sampler();
+
+        let mut rng = seeded_rng(9);
+        let samples = rng.sample_iter(&table).take(20_000);
+        let observed = Distribution::from_outcomes(samples.map(|sample| (sample, 1.0)));
+        let observed = PlotResult {
+            total: 20_000.0,
+            plot: observed.normalize(),
+        };
+
+        assert!(goodness_of_fit(&observed, &plot.plot).fits(0.001));
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_distributions_cant_be_sampled() {
+        Distribution::<f64>::empty().sampler();
+    }
+
+    #[test]
+    fn constants_are_always_sampled() {
+        let table = Distribution::<f64>::constant(7).sampler();
+
+        assert_eq!(table.sample_with(&mut seeded_rng(1)), 7);
+    }
+}
@@ -0,0 +1,235 @@
+//! Compare Module
+//!
+//! Compare two independent distributions directly, e.g. to decide between a
+//! +2 bonus and rolling with advantage.
+
+use crate::distribution::Distribution;
+use crate::operators::compare;
+use crate::traits::{Chance, PlotResult, RollResult, EPSILON};
+use crate::weight::Weight;
+
+/// Likelihood that a roll beats, ties or loses to another independent roll
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Matchup<W: Weight = Chance> {
+    pub win: W,
+    pub tie: W,
+    pub loss: W,
+}
+
+/// Summary of how one plot compares to another
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Versus {
+    /// likelihood that the first roll beats, ties or loses to the second
+    pub matchup: Matchup,
+    /// whether the first plot stochastically dominates the second
+    pub dominates: bool,
+    /// whether the second plot stochastically dominates the first
+    pub dominated: bool,
+    /// Kullback-Leibler divergence of the second plot from the first, or
+    /// `None` if the first plot has outcomes that are impossible in the
+    /// second, which makes it infinite
+    pub kl_divergence: Option<f64>,
+    /// Jensen-Shannon divergence between the two plots
+    pub js_divergence: f64,
+}
+
+impl<W: Weight> Distribution<W> {
+    /// Find the likelihood that an outcome of this distribution is greater
+    /// than, equal to or less than an outcome of another
+    pub fn matchup(&self, other: &Distribution<W>) -> Matchup<W> {
+        let outcomes = self.combine(other, compare);
+        if outcomes.is_empty() {
+            return Matchup {
+                win: W::zero(),
+                tie: W::zero(),
+                loss: W::zero(),
+            };
+        }
+
+        let outcomes = outcomes.normalize();
+        Matchup {
+            win: outcomes.chance(1),
+            tie: outcomes.chance(0),
+            loss: outcomes.chance(-1),
+        }
+    }
+}
+
+impl Distribution<Chance> {
+    /// the likelihood of rolling at least each outcome of either
+    /// distribution, for both distributions, in outcome order
+    fn paired_survival(&self, other: &Distribution<Chance>) -> Vec<(Chance, Chance)> {
+        let (low, high) = match (self.min(), other.min(), self.max(), other.max()) {
+            (Some(left_min), Some(right_min), Some(left_max), Some(right_max)) => {
+                (left_min.min(right_min), left_max.max(right_max))
+            }
+            _ => return vec![],
+        };
+        let (left_mass, right_mass) = (self.mass(), other.mass());
+
+        let (mut left_below, mut right_below) = (0.0, 0.0);
+        (low..=high)
+            .map(|value: RollResult| {
+                let survival = (1.0 - left_below, 1.0 - right_below);
+                left_below += self.chance(value) / left_mass;
+                right_below += other.chance(value) / right_mass;
+                survival
+            })
+            .collect()
+    }
+
+    /// Whether this distribution has first-order stochastic dominance over
+    /// another: it's at least as likely to roll any value or higher, and more
+    /// likely for some value
+    pub fn dominates(&self, other: &Distribution<Chance>) -> bool {
+        let survival = self.paired_survival(other);
+
+        survival
+            .iter()
+            .all(|(left, right)| left >= &(right - EPSILON))
+            && survival
+                .iter()
+                .any(|(left, right)| left > &(right + EPSILON))
+    }
+
+    /// the Kullback-Leibler divergence of another distribution from this one,
+    /// in nats: how much information is lost when this distribution is
+    /// approximated by the other
+    ///
+    /// This is infinite if this distribution has outcomes that are impossible
+    /// in the other, which is `None`.
+    pub fn kl_divergence(&self, other: &Distribution<Chance>) -> Option<f64> {
+        let (left_mass, right_mass) = (self.mass(), other.mass());
+
+        self.iter()
+            .filter(|(_, chance)| *chance != 0.0)
+            .map(|(value, chance)| {
+                let left = chance / left_mass;
+                let right = other.chance(value) / right_mass;
+                if right == 0.0 {
+                    return None;
+                }
+                Some(left * (left / right).ln())
+            })
+            .sum()
+    }
+
+    /// the Jensen-Shannon divergence between two distributions, in nats: a
+    /// symmetric version of the Kullback-Leibler divergence that is at most
+    /// the natural logarithm of 2
+    pub fn js_divergence(&self, other: &Distribution<Chance>) -> f64 {
+        let (left, right) = (self.normalize(), other.normalize());
+        let middle = Distribution::mixture(vec![(0.5, &left), (0.5, &right)]);
+
+        // every outcome of either distribution is possible in the middle
+        let divergence = |side: &Distribution<Chance>| {
+            side.kl_divergence(&middle)
+                .expect("outcome is possible in the middle")
+        };
+        (divergence(&left) + divergence(&right)) / 2.0
+    }
+}
+
+impl PlotResult {
+    /// Compare this plot to another, as if both were rolled independently
+    pub fn versus(&self, other: &PlotResult) -> Versus {
+        Versus {
+            matchup: self.plot.matchup(&other.plot),
+            dominates: self.plot.dominates(&other.plot),
+            dominated: other.plot.dominates(&self.plot),
+            kl_divergence: self.plot.kl_divergence(&other.plot),
+            js_divergence: self.plot.js_divergence(&other.plot),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::Expression;
+    use crate::test_helpers::assert_close;
+    use crate::traits::Rollable;
+    use crate::weight::Exact;
+
+    fn bonus(sides: u32, bonus: i32) -> Expression {
+        Expression::Sum(
+            Box::new(Expression::Die(sides)),
+            Box::new(Expression::Constant(bonus)),
+        )
+    }
+
+    #[test]
+    fn matchup_of_equal_dice() {
+        let d6: Distribution<Exact> = Distribution::die(6);
+
+        let actual = d6.matchup(&d6);
+
+        assert_eq!(actual.win, Exact::ratio(5, 12));
+        assert_eq!(actual.tie, Exact::ratio(1, 6));
+        assert_eq!(actual.loss, Exact::ratio(5, 12));
+    }
+
+    #[test]
+    fn matchup_of_bonus() {
+        let d20: Distribution<Exact> = Distribution::die(20);
+        let plus_two: Distribution<Exact> = bonus(20, 2).plot_as();
+
+        let actual = plus_two.matchup(&d20);
+
+        // ties only when the d20 rolls 2 higher: 18 of 400 outcomes
+        assert_eq!(actual.tie, Exact::ratio(18, 400));
+        assert_eq!(actual.loss, Exact::ratio(153, 400));
+        assert_eq!(actual.win, Exact::ratio(229, 400));
+        assert_eq!(
+            Distribution::<Exact>::empty().matchup(&d20).win,
+            Exact::zero()
+        );
+    }
+
+    #[test]
+    fn stochastic_dominance() {
+        let d20 = Expression::Die(20).plot().plot;
+        let plus_two = bonus(20, 2).plot().plot;
+        let advantage = Expression::Advantage(Box::new(Expression::Die(20)))
+            .plot()
+            .plot;
+
+        assert!(plus_two.dominates(&d20));
+        assert!(advantage.dominates(&d20));
+        assert!(!d20.dominates(&plus_two));
+        assert!(!d20.dominates(&d20));
+        // +2 is better for hard targets, advantage is better for easy ones
+        assert!(!plus_two.dominates(&advantage));
+        assert!(!advantage.dominates(&plus_two));
+    }
+
+    #[test]
+    fn divergence() {
+        let d4 = Expression::Die(4).plot().plot;
+        let d6 = Expression::Die(6).plot().plot;
+
+        assert_close(0.0, d6.kl_divergence(&d6).unwrap());
+        assert_close((6.0f64 / 4.0).ln(), d4.kl_divergence(&d6).unwrap());
+        assert_eq!(d6.kl_divergence(&d4), None);
+
+        // disjoint distributions are as far apart as possible
+        let high = bonus(4, 10).plot().plot;
+        assert_close(2.0f64.ln(), d4.js_divergence(&high));
+        assert_close(d4.js_divergence(&d6), d6.js_divergence(&d4));
+    }
+
+    #[test]
+    fn versus_summary() {
+        let plus_two = bonus(20, 2).plot();
+        let d20 = Expression::Die(20).plot();
+
+        let actual = plus_two.versus(&d20);
+
+        assert_close(229.0 / 400.0, actual.matchup.win);
+        assert!(actual.dominates);
+        assert!(!actual.dominated);
+        // a d20 can't roll the 21 or 22 of a +2
+        assert_eq!(actual.kl_divergence, None);
+        assert!(actual.js_divergence > 0.0);
+    }
+}
@@ -0,0 +1,468 @@
+//! Distribution Module
+
+use crate::operators::BinaryOperator;
+use crate::traits::{Chance, PlotTable, RollResult};
+use crate::weight::Weight;
+use std::collections::HashMap;
+
+/// Likelihood of every outcome between the lowest and highest possible
+/// outcomes, stored densely in outcome order
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Distribution<W: Weight = Chance> {
+    /// the lowest possible outcome
+    offset: RollResult,
+    /// likelihood of each outcome, starting at `offset`
+    chances: Vec<W>,
+}
+
+impl<W: Weight> Distribution<W> {
+    /// Create a distribution from the likelihood of each outcome, starting at
+    /// `offset`
+    ///
+    /// impossible outcomes at either end are trimmed, so that the first and
+    /// last outcomes of a distribution are always possible
+    pub fn new(offset: RollResult, mut chances: Vec<W>) -> Distribution<W> {
+        let start = match chances.iter().position(|chance| !chance.is_zero()) {
+            Some(start) => start,
+            None => return Distribution::empty(),
+        };
+        let end = chances
+            .iter()
+            .rposition(|chance| !chance.is_zero())
+            .expect("distribution has a possible outcome")
+            + 1;
+
+        chances.truncate(end);
+        chances.drain(..start);
+
+        Distribution {
+            offset: offset + start as RollResult,
+            chances,
+        }
+    }
+
+    /// Create a distribution without any possible outcomes
+    pub fn empty() -> Distribution<W> {
+        Distribution {
+            offset: 0,
+            chances: vec![],
+        }
+    }
+
+    /// Create a distribution with a single, certain outcome
+    pub fn constant(value: RollResult) -> Distribution<W> {
+        Distribution {
+            offset: value,
+            chances: vec![W::one()],
+        }
+    }
+
+    /// Create a distribution where every outcome from 1 to `sides` is equally
+    /// likely. a die without sides has no possible outcomes
+    pub fn die(sides: u32) -> Distribution<W> {
+        if sides == 0 {
+            return Distribution::empty();
+        }
+        Distribution::new(1, vec![W::ratio(1, u64::from(sides)); sides as usize])
+    }
+
+    /// Create a distribution from a list of outcomes and their likelihood,
+    /// combining the likelihood of repeated outcomes
+    pub fn from_outcomes<I>(outcomes: I) -> Distribution<W>
+    where
+        I: IntoIterator<Item = (RollResult, W)>,
+    {
+        let outcomes: Vec<(RollResult, W)> = outcomes.into_iter().collect();
+        let lowest = outcomes.iter().map(|(value, _)| *value).min();
+        let highest = outcomes.iter().map(|(value, _)| *value).max();
+
+        let (lowest, highest) = match (lowest, highest) {
+            (Some(lowest), Some(highest)) => (lowest, highest),
+            _ => return Distribution::empty(),
+        };
+
+        let mut chances = vec![W::zero(); (i64::from(highest) - i64::from(lowest)) as usize + 1];
+        for (value, chance) in outcomes {
+            let index = (i64::from(value) - i64::from(lowest)) as usize;
+            chances[index] = chances[index].plus(&chance);
+        }
+
+        Distribution::new(lowest, chances)
+    }
+
+    /// Export the possible outcomes of this distribution into a table
+    pub fn to_table(&self) -> PlotTable {
+        self.iter()
+            .filter(|(_, chance)| !chance.is_zero())
+            .map(|(value, chance)| (value, chance.to_f64()))
+            .collect()
+    }
+
+    /// Convert the likelihood of every outcome into a probability
+    pub fn to_chances(&self) -> Distribution<Chance> {
+        Distribution::new(
+            self.offset,
+            self.chances.iter().map(|chance| chance.to_f64()).collect(),
+        )
+    }
+
+    /// the lowest possible outcome, if there are any possible outcomes
+    pub fn min(&self) -> Option<RollResult> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.offset)
+        }
+    }
+
+    /// the highest possible outcome, if there are any possible outcomes
+    pub fn max(&self) -> Option<RollResult> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.offset + self.chances.len() as RollResult - 1)
+        }
+    }
+
+    /// the number of outcomes between the lowest and highest outcomes
+    pub fn len(&self) -> usize {
+        self.chances.len()
+    }
+
+    /// whether there are no possible outcomes
+    pub fn is_empty(&self) -> bool {
+        self.chances.is_empty()
+    }
+
+    /// the likelihood of each outcome, starting at the lowest outcome
+    pub fn chances(&self) -> &[W] {
+        &self.chances
+    }
+
+    /// the likelihood of a single outcome
+    pub fn chance(&self, value: RollResult) -> W {
+        let index = i64::from(value) - i64::from(self.offset);
+        if index < 0 {
+            return W::zero();
+        }
+
+        self.chances
+            .get(index as usize)
+            .cloned()
+            .unwrap_or_else(W::zero)
+    }
+
+    /// the combined likelihood of every outcome
+    pub fn mass(&self) -> W {
+        self.chances
+            .iter()
+            .fold(W::zero(), |total, chance| total.plus(chance))
+    }
+
+    /// Scale the likelihood of every outcome so that they add up to a
+    /// certainty
+    pub fn normalize(&self) -> Distribution<W> {
+        let mass = self.mass();
+        Distribution {
+            offset: self.offset,
+            chances: self
+                .chances
+                .iter()
+                .map(|chance| chance.divide(&mass))
+                .collect(),
+        }
+    }
+
+    /// the likelihood of rolling `value` or lower
+    pub fn chance_at_most(&self, value: RollResult) -> W {
+        self.iter()
+            .take_while(|(outcome, _)| *outcome <= value)
+            .fold(W::zero(), |total, (_, chance)| total.plus(&chance))
+    }
+
+    /// the likelihood of rolling `value` or higher, e.g. the chance to meet
+    /// a difficulty class
+    pub fn chance_at_least(&self, value: RollResult) -> W {
+        self.iter()
+            .skip_while(|(outcome, _)| *outcome < value)
+            .fold(W::zero(), |total, (_, chance)| total.plus(&chance))
+    }
+
+    /// the likelihood of rolling between `low` and `high`, inclusive
+    pub fn chance_between(&self, low: RollResult, high: RollResult) -> W {
+        self.iter()
+            .skip_while(|(outcome, _)| *outcome < low)
+            .take_while(|(outcome, _)| *outcome <= high)
+            .fold(W::zero(), |total, (_, chance)| total.plus(&chance))
+    }
+
+    /// the likelihood of rolling each outcome or lower, in outcome order
+    pub fn cumulative(&self) -> Vec<(RollResult, W)> {
+        let mut total = W::zero();
+        self.iter()
+            .map(|(value, chance)| {
+                total = total.plus(&chance);
+                (value, total.clone())
+            })
+            .collect()
+    }
+
+    /// the likelihood of rolling each outcome or higher, in outcome order
+    pub fn survival(&self) -> Vec<(RollResult, W)> {
+        let mut total = W::zero();
+        let mut survival: Vec<(RollResult, W)> = self
+            .chances
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, chance)| {
+                total = total.plus(chance);
+                (self.offset + index as RollResult, total.clone())
+            })
+            .collect();
+        survival.reverse();
+
+        survival
+    }
+
+    /// Iterate over every outcome between the lowest and highest outcome in
+    /// order, along with its likelihood
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (RollResult, W)> + 'a {
+        let offset = self.offset;
+        self.chances
+            .iter()
+            .enumerate()
+            .map(move |(index, chance)| (offset + index as RollResult, chance.clone()))
+    }
+
+    /// Find the distribution of the sum of this and another independent
+    /// distribution
+    pub fn convolve(&self, other: &Distribution<W>) -> Distribution<W> {
+        if self.is_empty() || other.is_empty() {
+            return Distribution::empty();
+        }
+
+        let mut chances = vec![W::zero(); self.chances.len() + other.chances.len() - 1];
+        for (left_index, left_chance) in self.chances.iter().enumerate() {
+            if left_chance.is_zero() {
+                continue;
+            }
+            for (right_index, right_chance) in other.chances.iter().enumerate() {
+                let index = left_index + right_index;
+                chances[index] = chances[index].plus(&left_chance.times(right_chance));
+            }
+        }
+
+        Distribution::new(self.offset + other.offset, chances)
+    }
+
+    /// Find the distribution of the sum of `count` independent copies of
+    /// this distribution by exponentiation-by-squaring
+    pub fn convolve_power(&self, count: u32) -> Distribution<W> {
+        let mut result = Distribution::constant(0);
+        let mut base = self.clone();
+        let mut count = count;
+
+        while count > 0 {
+            if count & 1 == 1 {
+                result = result.convolve(&base);
+            }
+            count >>= 1;
+            if count > 0 {
+                base = base.convolve(&base);
+            }
+        }
+
+        result
+    }
+
+    /// Find the distribution of an operator applied to every combination of
+    /// outcomes from this and another independent distribution
+    pub fn combine(&self, other: &Distribution<W>, operator: BinaryOperator) -> Distribution<W> {
+        let mut product: HashMap<RollResult, W> = HashMap::new();
+
+        for (left_value, left_chance) in self.iter().filter(|(_, chance)| !chance.is_zero()) {
+            for (right_value, right_chance) in other.iter().filter(|(_, chance)| !chance.is_zero())
+            {
+                let value = (operator)(&left_value, &right_value);
+                let chance = product.entry(value).or_insert_with(W::zero);
+                *chance = chance.plus(&left_chance.times(&right_chance));
+            }
+        }
+
+        Distribution::from_outcomes(product)
+    }
+
+    /// Find the distribution of picking one of several distributions, given
+    /// the likelihood of picking each one
+    pub fn mixture<'a, I>(options: I) -> Distribution<W>
+    where
+        W: 'a,
+        I: IntoIterator<Item = (W, &'a Distribution<W>)>,
+    {
+        Distribution::from_outcomes(options.into_iter().flat_map(|(option_chance, option)| {
+            option
+                .iter()
+                .map(move |(value, chance)| (value, option_chance.times(&chance)))
+        }))
+    }
+}
+
+impl Distribution<Chance> {
+    /// Create a distribution from a table of outcomes
+    pub fn from_table(table: &PlotTable) -> Distribution<Chance> {
+        Distribution::from_outcomes(table.iter().map(|(value, chance)| (*value, *chance)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operators::multiply;
+    use crate::weight::{Exact, LogChance};
+
+    #[test]
+    fn new_trims_impossible_outcomes() {
+        let distribution: Distribution = Distribution::new(0, vec![0.0, 0.0, 0.5, 0.0, 0.5, 0.0]);
+
+        assert_eq!(distribution.min(), Some(2));
+        assert_eq!(distribution.max(), Some(4));
+        assert_eq!(distribution.chances(), &[0.5, 0.0, 0.5]);
+        assert_eq!(
+            Distribution::new(3, vec![0.0, 0.0]),
+            Distribution::<Chance>::empty()
+        );
+    }
+
+    #[test]
+    fn iter_is_in_outcome_order() {
+        let distribution: Distribution =
+            Distribution::from_outcomes(vec![(3, 0.25), (-1, 0.5), (1, 0.25)]);
+
+        let actual: Vec<(RollResult, Chance)> = distribution.iter().collect();
+
+        assert_eq!(
+            actual,
+            vec![(-1, 0.5), (0, 0.0), (1, 0.25), (2, 0.0), (3, 0.25)]
+        );
+    }
+
+    #[test]
+    fn chance_is_zero_outside_of_outcomes() {
+        let distribution: Distribution = Distribution::die(4);
+
+        assert_eq!(distribution.chance(0), 0.0);
+        assert_eq!(distribution.chance(1), 0.25);
+        assert_eq!(distribution.chance(4), 0.25);
+        assert_eq!(distribution.chance(5), 0.0);
+    }
+
+    #[test]
+    fn table_round_trip_skips_impossible_outcomes() {
+        let table: PlotTable = [(1, 0.5), (3, 0.5)].iter().cloned().collect();
+
+        let distribution = Distribution::from_table(&table);
+
+        assert_eq!(distribution.len(), 3);
+        assert_eq!(distribution.to_table(), table);
+    }
+
+    #[test]
+    fn convolve_power_matches_repeated_convolve() {
+        let die: Distribution = Distribution::die(4);
+        let expected = die
+            .convolve(&die)
+            .convolve(&die)
+            .convolve(&die)
+            .convolve(&die);
+
+        let actual = die.convolve_power(5);
+
+        assert_eq!(actual.min(), Some(5));
+        assert_eq!(actual.max(), Some(20));
+        for ((expected_value, expected_chance), (actual_value, actual_chance)) in
+            expected.iter().zip(actual.iter())
+        {
+            assert_eq!(expected_value, actual_value);
+            assert!((expected_chance - actual_chance).abs() < 1e-6);
+        }
+        assert_eq!(die.convolve_power(0), Distribution::constant(0));
+    }
+
+    #[test]
+    fn combine_applies_operator() {
+        let die: Distribution = Distribution::die(2);
+
+        let actual = die.combine(&die, multiply);
+
+        assert_eq!(
+            actual.to_table(),
+            [(1, 0.25), (2, 0.5), (4, 0.25)].iter().cloned().collect()
+        );
+    }
+
+    #[test]
+    fn mixture_weighs_options() {
+        let one: Distribution = Distribution::constant(1);
+        let two = Distribution::constant(2);
+
+        let actual = Distribution::mixture(vec![(0.75, &one), (0.25, &two)]);
+
+        assert_eq!(actual.chances(), &[0.75, 0.25]);
+    }
+
+    #[test]
+    fn normalize_scales_to_certainty() {
+        let distribution: Distribution<Exact> =
+            Distribution::new(1, vec![Exact::ratio(1, 8), Exact::ratio(3, 8)]);
+
+        let actual = distribution.normalize();
+
+        assert_eq!(actual.chances(), &[Exact::ratio(1, 4), Exact::ratio(3, 4)]);
+    }
+
+    #[test]
+    fn range_queries() {
+        let distribution: Distribution = Distribution::die(20);
+
+        assert!((distribution.chance_at_least(15) - 0.3).abs() < 1e-12);
+        assert!((distribution.chance_at_most(5) - 0.25).abs() < 1e-12);
+        assert!((distribution.chance_between(5, 10) - 0.3).abs() < 1e-12);
+        assert_eq!(distribution.chance_at_least(21), 0.0);
+        assert!((distribution.chance_at_least(-4) - 1.0).abs() < 1e-12);
+        assert_eq!(distribution.chance_between(10, 5), 0.0);
+    }
+
+    #[test]
+    fn cumulative_and_survival_tables() {
+        let distribution: Distribution<Exact> = Distribution::die(2).convolve_power(2);
+
+        assert_eq!(
+            distribution.cumulative(),
+            vec![
+                (2, Exact::ratio(1, 4)),
+                (3, Exact::ratio(3, 4)),
+                (4, Exact::ratio(1, 1)),
+            ]
+        );
+        assert_eq!(
+            distribution.survival(),
+            vec![
+                (2, Exact::ratio(1, 1)),
+                (3, Exact::ratio(3, 4)),
+                (4, Exact::ratio(1, 4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn convolve_works_with_any_weight() {
+        let exact: Distribution<Exact> = Distribution::die(6).convolve_power(3);
+        let log: Distribution<LogChance> = Distribution::die(6).convolve_power(3);
+
+        assert_eq!(exact.chance(10), Exact::ratio(27, 216));
+        assert_eq!(exact.mass(), Exact::one());
+        assert!((log.chance(10).to_f64() - 27.0 / 216.0).abs() < 1e-12);
+        assert_eq!(exact.to_chances().min(), Some(3));
+    }
+}
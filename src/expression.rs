@@ -1,10 +1,14 @@
 //! Expression Module
 
+use crate::distribution::Distribution;
+use crate::random::below;
+use crate::traits::Chance;
 use crate::traits::PlotResult;
-use crate::traits::PlotTable;
 use crate::traits::RollResult;
+use crate::weight::Weight;
 use rand::Rng;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 
 use crate::operators::{
     advantage, difference, disadvantage, divide, equal_to, greater_than, greater_than_or_equal_to,
@@ -23,10 +27,53 @@ pub enum Comparison {
 
 use Comparison::*;
 
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = match self {
+            GreaterThan => ">",
+            GreaterThanOrEqualTo => ">=",
+            LessThan => "<",
+            LessThanOrEqualTo => "<=",
+            EqualTo => "=",
+        };
+        write!(f, "{}", operator)
+    }
+}
+
+impl Comparison {
+    /// retrieve the operator that performs this comparison
+    pub(crate) fn operator(&self) -> BinaryOperator {
+        match self {
+            GreaterThan => greater_than,
+            GreaterThanOrEqualTo => greater_than_or_equal_to,
+            LessThan => less_than,
+            LessThanOrEqualTo => less_than_or_equal_to,
+            EqualTo => equal_to,
+        }
+    }
+}
+
+/// A question about an expression that is answered with a single number
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// the chance that the expression is not zero, e.g. that a comparison
+    /// succeeds: `P(2d6 >= 8)`
+    Probability,
+    /// the expected value of the expression: `E[2d6 + 3]`
+    Expectation,
+    /// the variance of the expression: `Var[2d6 + 3]`
+    Variance,
+}
+
 /// Represents a dice roll expression
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expression {
     Die(u32),
+    /// sum of a pool of `count` dice with `sides` faces, e.g. `4d6`
+    Dice {
+        count: u32,
+        sides: u32,
+    },
     Constant(i32),
 
     Sum(Box<Expression>, Box<Expression>),
@@ -36,17 +83,116 @@ pub enum Expression {
     Advantage(Box<Expression>),
     Disadvantage(Box<Expression>),
     Compare(Box<Expression>, Box<Expression>, Comparison),
+    /// number of dice in a pool of `count` dice with `sides` faces that
+    /// satisfy a comparison against a face: `Count(comparison, face, count, sides)`
+    Count(Comparison, RollResult, u32, u32),
+    /// one of several expressions, picked at random according to its
+    /// weight relative to the sum of all weights, which must fit in a `u32`
+    Choice(Vec<(u32, Expression)>),
+    /// a question about an expression, answered by `Expression::evaluate`.
+    /// rolling or plotting a query rolls or plots the expression it asks
+    /// about
+    Query(Query, Box<Expression>),
+    /// the value bound to a name by `Let`
+    Variable(String),
+    /// bind the outcome of an expression to a name, so that it can be
+    /// shared by every `Variable` of that name in the body:
+    /// `Let(name, value, body)`
+    Let(String, Box<Expression>, Box<Expression>),
+    /// an expression, only counting outcomes where the condition is not
+    /// zero: `Given(expression, condition)`
+    ///
+    /// the condition only changes the outcome of the expression through the
+    /// variables it uses. in `d20 | d20 > 1`, the two d20 are separate
+    /// rolls, so `parse` rejects conditions that use no variable. Bind the
+    /// roll with `let a = d20 in a | a > 1` instead
+    Given(Box<Expression>, Box<Expression>),
+    /// an expression tagged with a label, such as a damage type:
+    /// `2d6[fire]`. labels don't change the outcome, but every labelled
+    /// roll is reported by `Expression::roll_labels`
+    Label(String, Box<Expression>),
 }
 
 use Expression::*;
 
+/// Outcomes bound to variable names
+pub(crate) type Scope = HashMap<String, RollResult>;
+
+/// Outcome of a roll, along with the sum of every labelled roll for each
+/// label
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Breakdown {
+    pub total: RollResult,
+    /// every label in order of appearance, with the sum of its rolls
+    pub labels: Vec<(String, RollResult)>,
+}
+
+/// Likelihood of every pair of an outcome and the sum of the rolls with a
+/// label
+type LabelPlot<W> = BTreeMap<(RollResult, RollResult), W>;
+
+/// Add the likelihood of a pair of an outcome and the sum of the rolls with a
+/// label to a plot
+fn add_pair<W: Weight>(plot: &mut LabelPlot<W>, pair: (RollResult, RollResult), chance: W) {
+    if chance.is_zero() {
+        return;
+    }
+    let total = plot.entry(pair).or_insert_with(W::zero);
+    *total = total.plus(&chance);
+}
+
+/// How many times a roll with conditions is attempted before giving up
+pub(crate) const MAX_ATTEMPTS: u32 = 100_000;
+
+/// Error for a roll where none of the attempts met every condition, such as
+/// `let a = d4 in a | a > 10`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnmetCondition;
+
+impl fmt::Display for UnmetCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "conditions of the expression could not be met")
+    }
+}
+
+impl std::error::Error for UnmetCondition {}
+
+/// Reasons that `Expression::evaluate` has no answer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryError {
+    /// the expression is not a query
+    NotAQuery,
+    /// the expression asked about has no possible outcomes, such as
+    /// `let a = d4 in a | a > 10`, whose condition is never met
+    NoOutcomes,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::NotAQuery => write!(f, "expression is not a query"),
+            QueryError::NoOutcomes => write!(f, "expression has no possible outcomes"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
 impl Expression {
     /// retrieve the operation encapsulated by the given `Expression`,
     /// represented by a binary operator and left/right expressions
-    fn get_operation(&self) -> Option<(BinaryOperator, &Box<Expression>, &Box<Expression>)> {
+    pub(crate) fn get_operation(&self) -> Option<(BinaryOperator, &Expression, &Expression)> {
         match self {
             Constant(_) => None,
             Die(_) => None,
+            Dice { .. } => None,
+            Count(..) => None,
+            Choice(_) => None,
+            Query(..) => None,
+            Variable(_) => None,
+            Let(..) => None,
+            Given(..) => None,
+            Label(..) => None,
 
             Sum(left, right) => Some((sum, left, right)),
             Diff(left, right) => Some((difference, left, right)),
@@ -54,82 +200,749 @@ impl Expression {
             Divide(left, right) => Some((divide, left, right)),
             Advantage(expr) => Some((advantage, expr, expr)),
             Disadvantage(expr) => Some((disadvantage, expr, expr)),
-            Compare(left, right, comparison) => {
-                let compare = match comparison {
-                    GreaterThan => greater_than,
-                    GreaterThanOrEqualTo => greater_than_or_equal_to,
-                    LessThan => less_than,
-                    LessThanOrEqualTo => less_than_or_equal_to,
-                    EqualTo => equal_to,
+            Compare(left, right, comparison) => Some((comparison.operator(), left, right)),
+        }
+    }
+
+    /// the expressions directly contained by this expression
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Constant(_) | Die(_) | Dice { .. } | Count(..) | Variable(_) => vec![],
+            Choice(options) => options.iter().map(|(_, option)| option).collect(),
+            Query(_, expr) | Label(_, expr) | Advantage(expr) | Disadvantage(expr) => vec![expr],
+            Sum(left, right)
+            | Diff(left, right)
+            | Multiply(left, right)
+            | Divide(left, right)
+            | Compare(left, right, _)
+            | Let(_, left, right)
+            | Given(left, right) => vec![left, right],
+        }
+    }
+
+    /// whether this expression only counts outcomes that meet a condition
+    pub fn is_conditional(&self) -> bool {
+        match self {
+            Given(..) => true,
+            _ => self.children().iter().any(|child| child.is_conditional()),
+        }
+    }
+
+    /// the names of variables that are used without being bound by a `Let`
+    pub fn unbound_variables(&self) -> Vec<&str> {
+        match self {
+            Variable(name) => vec![name.as_str()],
+            Let(name, value, body) => {
+                let mut unbound = value.unbound_variables();
+                unbound.extend(
+                    body.unbound_variables()
+                        .into_iter()
+                        .filter(|unbound| unbound != name),
+                );
+                unbound
+            }
+            _ => self
+                .children()
+                .iter()
+                .flat_map(|child| child.unbound_variables())
+                .collect(),
+        }
+    }
+
+    /// Answer the question asked by a query
+    pub fn evaluate(&self) -> Result<f64, QueryError> {
+        let (query, expr) = match self {
+            Query(query, expr) => (query, expr),
+            _ => return Err(QueryError::NotAQuery),
+        };
+        let plot = expr.plot_as::<Chance>();
+        if plot.mass() == 0.0 {
+            return Err(QueryError::NoOutcomes);
+        }
+
+        let answer = match query {
+            Query::Probability => 1.0 - plot.chance(0) / plot.mass(),
+            Query::Expectation => plot.mean(),
+            Query::Variance => plot.variance(),
+        };
+
+        Ok(answer)
+    }
+
+    /// the total number of possible outcomes of this expression, as
+    /// described by `PlotResult::total`
+    fn outcomes(&self) -> f64 {
+        match self {
+            Constant(_) => 1.0,
+            Die(sides) => f64::from(*sides),
+            Dice { count, sides } | Count(_, _, count, sides) => {
+                f64::from(*sides).powi(*count as i32)
+            }
+            Choice(options) => {
+                // every option is split into the same number of outcomes,
+                // each worth a share of its weight
+                let weight: u32 = options.iter().map(|(weight, _)| weight).sum();
+                options
+                    .iter()
+                    .filter(|(option_weight, _)| *option_weight > 0)
+                    .map(|(_, option)| option.outcomes())
+                    .product::<f64>()
+                    * f64::from(weight)
+            }
+            Query(_, expr) | Label(_, expr) => expr.outcomes(),
+            Variable(_) => 1.0,
+            Let(_, left, right) | Given(left, right) => left.outcomes() * right.outcomes(),
+            _ => {
+                let (_, left, right) = self
+                    .get_operation()
+                    .expect("expression does not represent an operation");
+                left.outcomes() * right.outcomes()
+            }
+        }
+    }
+
+    /// a rough estimate of the number of steps needed to plot this
+    /// expression exactly, to decide whether it's cheaper to estimate it
+    pub fn plot_cost(&self) -> f64 {
+        self.plot_size().0
+    }
+
+    /// estimate the number of steps needed to plot this expression, along
+    /// with the number of outcomes in its plot
+    fn plot_size(&self) -> (f64, f64) {
+        match self {
+            Constant(_) | Variable(_) => (1.0, 1.0),
+            Die(sides) => (f64::from(*sides), f64::from(*sides)),
+            Dice { count, sides } => {
+                let width = f64::from(*count) * f64::from(sides.saturating_sub(1)) + 1.0;
+                // each squaring convolves plots up to the final width
+                let squarings = f64::from(32 - count.leading_zeros());
+                (width * width * squarings, width)
+            }
+            Count(_, _, count, _) => {
+                let width = f64::from(*count) + 1.0;
+                let squarings = f64::from(32 - count.leading_zeros());
+                (width * width * squarings, width)
+            }
+            Query(_, expr) | Label(_, expr) => expr.plot_size(),
+            Choice(options) => options.iter().map(|(_, option)| option.plot_size()).fold(
+                (0.0, 0.0),
+                |(cost, width), (option_cost, option_width)| {
+                    (cost + option_cost + option_width, width.max(option_width))
+                },
+            ),
+            Let(_, value, body) => {
+                let (value_cost, value_width) = value.plot_size();
+                let (body_cost, body_width) = body.plot_size();
+                // the body is plotted once for every outcome of the value
+                (
+                    value_cost + value_width * (body_cost + body_width),
+                    body_width,
+                )
+            }
+            Given(expr, condition) => {
+                let (expr_cost, width) = expr.plot_size();
+                let (condition_cost, _) = condition.plot_size();
+                (expr_cost + condition_cost + width, width)
+            }
+            _ => {
+                let (_, left, right) = self
+                    .get_operation()
+                    .expect("expression does not represent an operation");
+                let (left_cost, left_width) = left.plot_size();
+                let (right_cost, right_width) = right.plot_size();
+                let width = match self {
+                    Sum(..) | Diff(..) => left_width + right_width - 1.0,
+                    Compare(..) => 2.0,
+                    Advantage(..) | Disadvantage(..) => left_width,
+                    _ => left_width * right_width,
                 };
-                Some((compare, left, right))
+                (left_cost + right_cost + left_width * right_width, width)
             }
         }
     }
-}
 
-impl Rollable for Expression {
-    /// Get a single value from the roll expression
-    fn roll(&self) -> RollResult {
+    /// Create a distribution of all possible outcomes, with their likelihood
+    /// represented by any kind of `Weight`
+    ///
+    /// `Rollable::plot` uses double precision floats. Use `Exact` to get
+    /// exact odds, or `LogChance` to keep outcomes that are too unlikely to
+    /// be represented by a float.
+    pub fn plot_as<W: Weight>(&self) -> Distribution<W> {
+        self.plot_given().0
+    }
+
+    /// Create a distribution of all possible outcomes where every condition
+    /// is met, along with the chance that every condition is met
+    pub fn plot_given<W: Weight>(&self) -> (Distribution<W>, W) {
+        let plot = self.plot_in::<W>(&Scope::new());
+        if !self.is_conditional() {
+            return (plot, W::one());
+        }
+
+        let condition = plot.mass();
+        (plot.normalize(), condition)
+    }
+
+    /// Create a distribution of all possible outcomes with variables bound
+    /// to the given outcomes. Outcomes where a condition is not met are left
+    /// out, so the likelihoods may not add up to a certainty.
+    pub(crate) fn plot_in<W: Weight>(&self, scope: &Scope) -> Distribution<W> {
         // get the root cases out of the way
         if let Constant(num) = self {
-            return *num;
+            return Distribution::constant(*num);
         }
-        if let Die(max) = self {
-            return rand::thread_rng().gen_range(1, *max as RollResult + 1);
+        if let Die(num) = self {
+            return Distribution::die(*num);
+        }
+        if let Dice { count, sides } = self {
+            return Distribution::die(*sides).convolve_power(*count);
         }
+        if let Query(_, expr) | Label(_, expr) = self {
+            return expr.plot_in(scope);
+        }
+        if let Variable(name) = self {
+            let value = scope
+                .get(name)
+                .unwrap_or_else(|| panic!("unbound variable `{}`", name));
+            return Distribution::constant(*value);
+        }
+        if let Let(name, value, body) = self {
+            let value = value.plot_in::<W>(scope);
+            let bodies: Vec<(W, Distribution<W>)> = value
+                .iter()
+                .filter(|(_, chance)| !chance.is_zero())
+                .map(|(value, chance)| {
+                    let mut scope = scope.clone();
+                    scope.insert(name.clone(), value);
+                    (chance, body.plot_in(&scope))
+                })
+                .collect();
+
+            return Distribution::mixture(
+                bodies.iter().map(|(chance, body)| (chance.clone(), body)),
+            );
+        }
+        if let Given(expr, condition) = self {
+            let condition = condition
+                .plot_in::<W>(scope)
+                .iter()
+                .filter(|(value, _)| *value != 0)
+                .fold(W::zero(), |total, (_, chance)| total.plus(&chance));
+
+            return Distribution::mixture(vec![(condition, &expr.plot_in(scope))]);
+        }
+        if let Count(comparison, face, count, sides) = self {
+            let matches = comparison.operator();
+            let hits = (1..=*sides as RollResult)
+                .filter(|roll| matches(roll, face) == 1)
+                .count() as u64;
+            let sides = u64::from(*sides);
+
+            // binomial distribution, as the sum of whether each die matched
+            let die = match sides {
+                0 => Distribution::empty(),
+                _ => Distribution::new(
+                    0,
+                    vec![W::ratio(sides - hits, sides), W::ratio(hits, sides)],
+                ),
+            };
+
+            return die.convolve_power(*count);
+        }
+        if let Choice(options) = self {
+            let weight: u32 = options.iter().map(|(weight, _)| weight).sum();
+            let options: Vec<(W, Distribution<W>)> = options
+                .iter()
+                .filter(|(option_weight, _)| *option_weight > 0)
+                .map(|(option_weight, option)| {
+                    (
+                        W::ratio(u64::from(*option_weight), u64::from(weight)),
+                        option.plot_in(scope),
+                    )
+                })
+                .collect();
 
+            return Distribution::mixture(
+                options
+                    .iter()
+                    .map(|(option_chance, option)| (option_chance.clone(), option)),
+            );
+        }
+
+        // handle the more complicated expressions
         let (operator, left, right) = self
             .get_operation()
             .expect("expression does not represent an operation");
 
-        (operator)(&left.roll(), &right.roll())
+        let left = left.plot_in::<W>(scope);
+        let right = right.plot_in::<W>(scope);
+
+        // sums of independent rolls can use the faster convolution
+        match self {
+            Sum(..) => left.convolve(&right),
+            _ => left.combine(&right, operator),
+        }
     }
+}
 
-    /// Create a list of all possible outcomes and their possibility
-    fn plot(&self) -> PlotResult {
+/// Roll a single die with `sides` faces
+pub(crate) fn roll_die<R: Rng + ?Sized>(sides: u32, rng: &mut R) -> RollResult {
+    below(sides, rng) as RollResult + 1
+}
+
+/// Pick one of several options at random according to its weight relative
+/// to the sum of all weights
+pub(crate) fn pick_option<'a, R: Rng + ?Sized>(
+    options: &'a [(u32, Expression)],
+    rng: &mut R,
+) -> &'a Expression {
+    let total: u32 = options.iter().map(|(weight, _)| weight).sum();
+    let mut pick = below(total, rng);
+    let (_, option) = options
+        .iter()
+        .find(|(weight, _)| {
+            if pick < *weight {
+                return true;
+            }
+            pick -= weight;
+            false
+        })
+        .expect("choice must have a positive total weight");
+
+    option
+}
+
+impl Expression {
+    /// Get a single value from the roll expression, or `UnmetCondition` if
+    /// no roll met every condition
+    pub fn try_roll(&self) -> Result<RollResult, UnmetCondition> {
+        self.try_roll_with(&mut rand::thread_rng())
+    }
+
+    /// Get a single value from the roll expression using the given random
+    /// number generator, or `UnmetCondition` if no roll met every condition
+    ///
+    /// rolls that do not meet every condition are thrown out and rolled
+    /// again, up to `MAX_ATTEMPTS` times
+    pub fn try_roll_with<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Result<RollResult, UnmetCondition> {
+        (0..MAX_ATTEMPTS)
+            .filter_map(|_| self.roll_in(&Scope::new(), rng))
+            .next()
+            .ok_or(UnmetCondition)
+    }
+
+    /// Get a single value from the roll expression with variables bound to
+    /// the given outcomes, or `None` if a condition was not met
+    pub(crate) fn roll_in<R: Rng + ?Sized>(
+        &self,
+        scope: &Scope,
+        rng: &mut R,
+    ) -> Option<RollResult> {
+        self.roll_labels_in(scope, &mut vec![], rng)
+    }
+
+    /// Get a single value from the roll expression with variables bound to
+    /// the given outcomes, and add the outcome of every labelled roll to
+    /// `labels`, or `None` if a condition was not met
+    fn roll_labels_in<R: Rng + ?Sized>(
+        &self,
+        scope: &Scope,
+        labels: &mut Vec<(String, RollResult)>,
+        rng: &mut R,
+    ) -> Option<RollResult> {
         // get the root cases out of the way
         if let Constant(num) = self {
-            return PlotResult {
-                total: 1.0,
-                plot: [(*num, 1.0)].iter().cloned().collect(),
-            };
+            return Some(*num);
         }
-        if let Die(num) = self {
-            let total = *num as f32;
-            return PlotResult {
-                total,
-                plot: (1..num + 1)
-                    .map(|i| (i as RollResult, 1.0 / total))
-                    .collect(),
+        if let Die(max) = self {
+            return Some(roll_die(*max, rng));
+        }
+        if let Dice { count, sides } = self {
+            return Some((0..*count).map(|_| roll_die(*sides, rng)).sum());
+        }
+        if let Count(comparison, face, count, sides) = self {
+            let matches = comparison.operator();
+            return Some(
+                (0..*count)
+                    .map(|_| roll_die(*sides, rng))
+                    .filter(|roll| matches(roll, face) == 1)
+                    .count() as RollResult,
+            );
+        }
+        if let Query(_, expr) = self {
+            return expr.roll_labels_in(scope, labels, rng);
+        }
+        if let Label(label, expr) = self {
+            let start = labels.len();
+            let value = expr.roll_labels_in(scope, labels, rng)?;
+            // only the outermost of nested labels with the same name counts
+            let inner = labels.split_off(start);
+            labels.extend(inner.into_iter().filter(|(other, _)| other != label));
+            labels.push((label.clone(), value));
+            return Some(value);
+        }
+        if let Variable(name) = self {
+            let value = scope
+                .get(name)
+                .unwrap_or_else(|| panic!("unbound variable `{}`", name));
+            return Some(*value);
+        }
+        if let Let(name, value, body) = self {
+            let mut scope = scope.clone();
+            scope.insert(name.clone(), value.roll_labels_in(&scope, labels, rng)?);
+            return body.roll_labels_in(&scope, labels, rng);
+        }
+        if let Given(expr, condition) = self {
+            let value = expr.roll_labels_in(scope, labels, rng)?;
+            // labels in the condition don't count towards the outcome
+            return match condition.roll_in(scope, rng)? {
+                0 => None,
+                _ => Some(value),
             };
         }
+        if let Choice(options) = self {
+            return pick_option(options, rng).roll_labels_in(scope, labels, rng);
+        }
 
-        // handle the more complicated expressions
         let (operator, left, right) = self
             .get_operation()
             .expect("expression does not represent an operation");
+        let start = labels.len();
+        let left = left.roll_labels_in(scope, labels, rng)?;
+        let middle = labels.len();
+        let right = right.roll_labels_in(scope, labels, rng)?;
+        let value = (operator)(&left, &right);
+
+        // only the labels of the roll kept by advantage and disadvantage count
+        if let Advantage(_) | Disadvantage(_) = self {
+            if value == left {
+                labels.truncate(middle);
+            } else {
+                labels.drain(start..middle);
+            }
+        }
 
-        let left = left.plot();
-        let right = right.plot();
+        Some(value)
+    }
+}
 
-        let mut product: PlotTable = HashMap::new();
+impl Expression {
+    /// every label used in this expression, in order of appearance
+    pub fn labels(&self) -> Vec<&str> {
+        let mut labels = match self {
+            Label(label, _) => vec![label.as_str()],
+            _ => vec![],
+        };
+        for label in self.children().iter().flat_map(|child| child.labels()) {
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
 
-        left.plot
-            .iter()
-            .flat_map(|(left_value, left_chance)| {
-                right.plot.iter().map(move |(right_value, right_chance)| {
-                    let value = (operator)(left_value, right_value);
-                    (value, left_chance * right_chance)
-                })
+        labels
+    }
+
+    /// Get a single value from the roll expression, along with the sum of
+    /// every labelled roll for each label
+    ///
+    /// a label that is rolled more than once, e.g. by `d20[luck] + d4[luck]`,
+    /// adds up every roll, but only the roll kept by `adv` or `dis` counts.
+    /// a label inside another label with the same name only counts once, as
+    /// the outer one. labels in a condition are not counted. fails with
+    /// `UnmetCondition` if no roll met every condition.
+    pub fn roll_labels(&self) -> Result<Breakdown, UnmetCondition> {
+        self.roll_labels_with(&mut rand::thread_rng())
+    }
+
+    /// Get a single value from the roll expression, along with the sum of
+    /// every labelled roll for each label, using the given random number
+    /// generator
+    pub fn roll_labels_with<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Result<Breakdown, UnmetCondition> {
+        let (total, rolls) = (0..MAX_ATTEMPTS)
+            .filter_map(|_| {
+                let mut rolls = vec![];
+                let total = self.roll_labels_in(&Scope::new(), &mut rolls, rng)?;
+                Some((total, rolls))
             })
-            .for_each(|(value, count)| {
-                *product.entry(value).or_insert(0.0) += count;
-            });
+            .next()
+            .ok_or(UnmetCondition)?;
+
+        let labels = self
+            .labels()
+            .into_iter()
+            .map(|label| {
+                let value = rolls
+                    .iter()
+                    .filter(|(other, _)| other == label)
+                    .map(|(_, value)| value)
+                    .sum();
+                (String::from(label), value)
+            })
+            .collect();
+
+        Ok(Breakdown { total, labels })
+    }
+
+    /// Plot every pair of an outcome and the sum of the rolls with the given
+    /// label, with variables bound to the given outcomes. Outcomes where a
+    /// condition is not met are left out, so the likelihoods may not add up
+    /// to a certainty.
+    fn plot_label_in<W: Weight>(&self, label: &str, scope: &Scope) -> LabelPlot<W> {
+        let mut plot = LabelPlot::new();
+
+        match self {
+            Label(other, expr) => {
+                // only the outermost of nested labels with the same name
+                // counts
+                let labelled = other == label;
+                for ((value, sum), chance) in expr.plot_label_in::<W>(label, scope) {
+                    let sum = if labelled { value } else { sum };
+                    add_pair(&mut plot, (value, sum), chance);
+                }
+            }
+            Query(_, expr) => return expr.plot_label_in(label, scope),
+            // the parts without the label can use the faster plots
+            _ if !self.labels().contains(&label) => {
+                for (value, chance) in self.plot_in::<W>(scope).iter() {
+                    add_pair(&mut plot, (value, 0), chance);
+                }
+            }
+            Let(name, value, body) => {
+                // the rolls of the value are shared with the variable
+                for ((value, sum), chance) in value.plot_label_in::<W>(label, scope) {
+                    let mut scope = scope.clone();
+                    scope.insert(name.clone(), value);
+                    for ((outcome, body_sum), body_chance) in body.plot_label_in::<W>(label, &scope)
+                    {
+                        add_pair(
+                            &mut plot,
+                            (outcome, sum + body_sum),
+                            chance.times(&body_chance),
+                        );
+                    }
+                }
+            }
+            Given(expr, condition) => {
+                // labels in the condition don't count towards the outcome
+                let condition = condition
+                    .plot_in::<W>(scope)
+                    .iter()
+                    .filter(|(value, _)| *value != 0)
+                    .fold(W::zero(), |total, (_, chance)| total.plus(&chance));
+                for (pair, chance) in expr.plot_label_in::<W>(label, scope) {
+                    add_pair(&mut plot, pair, chance.times(&condition));
+                }
+            }
+            Choice(options) => {
+                let weight: u32 = options.iter().map(|(weight, _)| weight).sum();
+                for (option_weight, option) in options {
+                    let option_chance = W::ratio(u64::from(*option_weight), u64::from(weight));
+                    for (pair, chance) in option.plot_label_in::<W>(label, scope) {
+                        add_pair(&mut plot, pair, option_chance.times(&chance));
+                    }
+                }
+            }
+            _ => {
+                let (operator, left, right) = self
+                    .get_operation()
+                    .expect("expression does not represent an operation");
+                let right = right.plot_label_in::<W>(label, scope);
+                for ((left, left_sum), left_chance) in left.plot_label_in::<W>(label, scope) {
+                    for ((right, right_sum), right_chance) in right.iter() {
+                        let value = (operator)(&left, right);
+                        // only the labels of the roll kept by advantage and
+                        // disadvantage count
+                        let sum = match self {
+                            Advantage(_) | Disadvantage(_) if value == left => left_sum,
+                            Advantage(_) | Disadvantage(_) => *right_sum,
+                            _ => left_sum + right_sum,
+                        };
+                        add_pair(&mut plot, (value, sum), left_chance.times(right_chance));
+                    }
+                }
+            }
+        }
+
+        plot
+    }
 
+    /// Create a distribution of the sum of every roll with the given label
+    pub fn plot_label(&self, label: &str) -> Distribution<Chance> {
+        self.plot_label_as(label)
+    }
+
+    /// Create a distribution of the sum of every roll with the given label,
+    /// with their likelihood represented by any kind of `Weight`
+    ///
+    /// like `Expression::roll_labels`, only rolls where every condition is
+    /// met are counted
+    pub fn plot_label_as<W: Weight>(&self, label: &str) -> Distribution<W> {
+        let plot = self.plot_label_in::<W>(label, &Scope::new());
+        let sums =
+            Distribution::from_outcomes(plot.into_iter().map(|((_, sum), chance)| (sum, chance)));
+        if !self.is_conditional() {
+            return sums;
+        }
+
+        sums.normalize()
+    }
+
+    /// Scale every roll with the given label by `numerator / denominator`,
+    /// rounding towards zero, e.g. halving fire damage for resistance
+    pub fn with_multiplier(&self, label: &str, numerator: i32, denominator: i32) -> Expression {
+        let scale =
+            |expr: &Expression| Box::new(expr.with_multiplier(label, numerator, denominator));
+
+        match self {
+            // nested labels with the same name are only scaled once, as the
+            // outer one
+            Label(other, expr) if other == label => Label(
+                other.clone(),
+                Box::new(Divide(
+                    Box::new(Multiply(expr.clone(), Box::new(Constant(numerator)))),
+                    Box::new(Constant(denominator)),
+                )),
+            ),
+            Label(other, expr) => Label(other.clone(), scale(expr)),
+            Constant(_) | Die(_) | Dice { .. } | Count(..) | Variable(_) => self.clone(),
+            Choice(options) => Choice(
+                options
+                    .iter()
+                    .map(|(weight, option)| (*weight, *scale(option)))
+                    .collect(),
+            ),
+            Query(query, expr) => Query(query.clone(), scale(expr)),
+            Advantage(expr) => Advantage(scale(expr)),
+            Disadvantage(expr) => Disadvantage(scale(expr)),
+            Let(name, value, body) => Let(name.clone(), scale(value), scale(body)),
+            // the condition is not part of the outcome
+            Given(expr, condition) => Given(scale(expr), condition.clone()),
+            Sum(left, right) => Sum(scale(left), scale(right)),
+            Diff(left, right) => Diff(scale(left), scale(right)),
+            Multiply(left, right) => Multiply(scale(left), scale(right)),
+            Divide(left, right) => Divide(scale(left), scale(right)),
+            Compare(left, right, comparison) => {
+                Compare(scale(left), scale(right), comparison.clone())
+            }
+        }
+    }
+}
+
+impl Expression {
+    /// how loosely this expression binds when written out, where expressions
+    /// that bind more loosely need parentheses to be used as an operand
+    fn precedence(&self) -> u8 {
+        match self {
+            Multiply(..) | Divide(..) => 1,
+            Sum(..) | Diff(..) => 2,
+            Compare(..) => 3,
+            Let(..) | Given(..) | Query(..) => 4,
+            _ => 0,
+        }
+    }
+
+    /// write this expression as an operand of an expression that binds
+    /// tighter than `precedence`, adding parentheses when needed
+    fn fmt_operand(&self, f: &mut fmt::Formatter, precedence: u8) -> fmt::Result {
+        if self.precedence() >= precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    /// write the expression in the syntax accepted by `parse`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let precedence = self.precedence();
+
+        match self {
+            Die(sides) => write!(f, "d{}", sides),
+            Dice { count, sides } => write!(f, "{}d{}", count, sides),
+            Constant(value) => write!(f, "{}", value),
+            Variable(name) => write!(f, "{}", name),
+            Sum(left, right) | Diff(left, right) | Multiply(left, right) | Divide(left, right) => {
+                let operator = match self {
+                    Sum(..) => "+",
+                    Diff(..) => "-",
+                    Multiply(..) => "*",
+                    _ => "/",
+                };
+                // operators are left associative
+                left.fmt_operand(f, precedence + 1)?;
+                write!(f, " {} ", operator)?;
+                right.fmt_operand(f, precedence)
+            }
+            Compare(left, right, comparison) => {
+                left.fmt_operand(f, precedence)?;
+                write!(f, " {} ", comparison)?;
+                right.fmt_operand(f, precedence)
+            }
+            Advantage(expr) => write!(f, "adv({})", expr),
+            Disadvantage(expr) => write!(f, "dis({})", expr),
+            Count(comparison, face, count, sides) => {
+                write!(f, "count({}{}, {}d{})", comparison, face, count, sides)
+            }
+            Choice(options) => {
+                write!(f, "choose(")?;
+                for (index, (weight, option)) in options.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", weight)?;
+                    option.fmt_operand(f, 4)?;
+                }
+                write!(f, ")")
+            }
+            Query(Query::Probability, expr) => write!(f, "P({})", expr),
+            Query(Query::Expectation, expr) => write!(f, "E[{}]", expr),
+            Query(Query::Variance, expr) => write!(f, "Var[{}]", expr),
+            Let(name, value, body) => {
+                write!(f, "let {} = ", name)?;
+                value.fmt_operand(f, precedence)?;
+                write!(f, " in {}", body)
+            }
+            Given(expr, condition) => {
+                expr.fmt_operand(f, precedence)?;
+                write!(f, " | ")?;
+                condition.fmt_operand(f, precedence)
+            }
+            Label(label, expr) => {
+                match **expr {
+                    Label(..) => write!(f, "({})", expr)?,
+                    _ => expr.fmt_operand(f, 1)?,
+                }
+                write!(f, "[{}]", label)
+            }
+        }
+    }
+}
+
+impl Rollable for Expression {
+    /// Get a single value from the roll expression, using the given random
+    /// number generator
+    ///
+    /// rolls that do not meet every condition are thrown out and rolled
+    /// again. panics if no roll meets them, which `Expression::try_roll_with`
+    /// reports as an error instead
+    fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> RollResult {
+        self.try_roll_with(rng)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Create a list of all possible outcomes and their possibility
+    fn plot(&self) -> PlotResult {
         PlotResult {
-            total: left.total * right.total,
-            plot: product,
+            total: self.outcomes(),
+            plot: self.plot_as(),
         }
     }
 }
@@ -137,11 +950,14 @@ impl Rollable for Expression {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::weight::{Exact, LogChance};
+    use std::collections::HashMap;
 
     impl PlotResult {
         /// de-normalize the table of possible outcomes
         pub fn simplify(&self) -> HashMap<i32, i32> {
             self.plot
+                .to_table()
                 .iter()
                 .map(|(value, chance)| {
                     let outcomes = (chance * self.total) as i32;
@@ -155,10 +971,7 @@ mod tests {
     fn simplify_produces_correct_table() {
         let plot_result = PlotResult {
             total: 10.0,
-            plot: [(1, 0.1), (2, 0.2), (3, 0.3), (4, 0.4)]
-                .iter()
-                .cloned()
-                .collect(),
+            plot: Distribution::new(1, vec![0.1, 0.2, 0.3, 0.4]),
         };
         let expected: HashMap<i32, i32> =
             [(1, 1), (2, 2), (3, 3), (4, 4)].iter().cloned().collect();
@@ -389,4 +1202,693 @@ mod tests {
             assert_eq!(expected, actual, "{:?}", comparison);
         }
     }
+
+    #[test]
+    fn compare_is_not_symmetric() {
+        // identical dice can't tell `<` from `>`, so compare d4 against 2
+        let cases: Vec<(Comparison, &[(i32, i32)])> = vec![
+            (Comparison::GreaterThan, &[(0, 2), (1, 2)]),
+            (Comparison::GreaterThanOrEqualTo, &[(0, 1), (1, 3)]),
+            (Comparison::LessThan, &[(0, 3), (1, 1)]),
+            (Comparison::LessThanOrEqualTo, &[(0, 2), (1, 2)]),
+            (Comparison::EqualTo, &[(0, 3), (1, 1)]),
+        ];
+
+        for (comparison, options) in cases {
+            let expression = Expression::Compare(
+                Box::new(Expression::Die(4)),
+                Box::new(Expression::Constant(2)),
+                comparison.clone(),
+            );
+
+            let expected: HashMap<i32, i32> = options.iter().cloned().collect();
+            let actual = expression.plot().simplify();
+
+            assert_eq!(expected, actual, "{:?}", comparison);
+        }
+
+        let below = Expression::Count(Comparison::LessThan, 2, 1, 4);
+        assert_eq!(below.plot().plot.chance(1), 0.25);
+    }
+
+    #[test]
+    fn count_produces_correct_plot() {
+        // 1 1 -> 0
+        // 1 2 -> 0
+        // 1 3 -> 0
+        // 1 4 -> 1
+        // 2 1 -> 0
+        // 2 2 -> 0
+        // 2 3 -> 0
+        // 2 4 -> 1
+        // 3 1 -> 0
+        // 3 2 -> 0
+        // 3 3 -> 0
+        // 3 4 -> 1
+        // 4 1 -> 1
+        // 4 2 -> 1
+        // 4 3 -> 1
+        // 4 4 -> 2
+        let cases: Vec<(Expression, &[(i32, i32)])> = vec![
+            (
+                Expression::Count(Comparison::EqualTo, 4, 2, 4),
+                &[(0, 9), (1, 6), (2, 1)],
+            ),
+            (
+                Expression::Count(Comparison::GreaterThanOrEqualTo, 3, 2, 4),
+                &[(0, 4), (1, 8), (2, 4)],
+            ),
+            (
+                Expression::Count(Comparison::GreaterThan, 4, 2, 4),
+                &[(0, 16)],
+            ),
+            (
+                Expression::Count(Comparison::LessThan, 2, 2, 4),
+                &[(0, 9), (1, 6), (2, 1)],
+            ),
+            (
+                Expression::Count(Comparison::LessThanOrEqualTo, 2, 2, 4),
+                &[(0, 4), (1, 8), (2, 4)],
+            ),
+        ];
+
+        for (expression, options) in cases {
+            let expected: HashMap<i32, i32> = options.iter().cloned().collect();
+            let actual = expression.plot().simplify();
+
+            assert_eq!(expected, actual, "{:?}", expression);
+        }
+    }
+
+    #[test]
+    fn count_rolls_within_pool() {
+        let expression = Expression::Count(Comparison::EqualTo, 6, 5, 6);
+
+        for _ in 0..100 {
+            let actual = expression.roll();
+            assert!((0..=5).contains(&actual), "{}", actual);
+        }
+    }
+
+    #[test]
+    fn choice_produces_correct_plot() {
+        let expression = Expression::Choice(vec![(3, Expression::Die(2)), (1, Expression::Die(4))]);
+        // 3/4 * 1/2 + 1/4 * 1/4 -> 1
+        // 3/4 * 1/2 + 1/4 * 1/4 -> 2
+        //             1/4 * 1/4 -> 3
+        //             1/4 * 1/4 -> 4
+        let expected: HashMap<i32, i32> =
+            [(1, 14), (2, 14), (3, 2), (4, 2)].iter().cloned().collect();
+
+        let actual = expression.plot();
+
+        // a total weight of 4, times 2 and 4 outcomes
+        assert_eq!(actual.total, 32.0);
+        assert_eq!(expected, actual.simplify());
+    }
+
+    #[test]
+    fn choice_skips_options_without_weight() {
+        let expression = Expression::Choice(vec![
+            (0, Expression::Constant(1)),
+            (1, Expression::Constant(2)),
+            (0, Expression::Constant(3)),
+        ]);
+
+        for _ in 0..100 {
+            assert_eq!(expression.roll(), 2);
+        }
+    }
+
+    #[test]
+    fn dice_produces_correct_plot() {
+        let expression = Expression::Dice { count: 3, sides: 4 };
+        let expected: HashMap<i32, i32> = [
+            (3, 1),
+            (4, 3),
+            (5, 6),
+            (6, 10),
+            (7, 12),
+            (8, 12),
+            (9, 10),
+            (10, 6),
+            (11, 3),
+            (12, 1),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        let actual = expression.plot().simplify();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn dice_matches_nested_sums() {
+        let nested = (1..5).fold(Expression::Die(6), |acc, _| {
+            Expression::Sum(Box::new(acc), Box::new(Expression::Die(6)))
+        });
+        let dice = Expression::Dice { count: 5, sides: 6 };
+
+        let expected = nested.plot();
+        let actual = dice.plot();
+
+        assert_eq!(expected.total, actual.total);
+        assert_eq!(expected.plot.len(), actual.plot.len());
+        for (value, chance) in expected.plot.iter() {
+            let difference = (actual.plot.chance(value) - chance).abs();
+            assert!(difference < 1e-6, "{}: {}", value, difference);
+        }
+    }
+
+    #[test]
+    fn dice_plots_large_pools() {
+        let expression = Expression::Dice {
+            count: 200,
+            sides: 20,
+        };
+
+        let actual = expression.plot();
+
+        assert_eq!(actual.plot.len(), 200 * 20 - 200 + 1);
+        let total: Chance = actual.plot.chances().iter().sum();
+        assert!((total - 1.0).abs() < 1e-3, "{}", total);
+    }
+
+    #[test]
+    fn dice_with_no_dice_is_zero() {
+        let expression = Expression::Dice { count: 0, sides: 6 };
+
+        assert_eq!(expression.roll(), 0);
+        assert_eq!(expression.plot().plot, Distribution::constant(0));
+    }
+
+    #[test]
+    fn dice_without_sides_have_no_outcomes() {
+        let cases = vec![
+            Expression::Die(0),
+            Expression::Dice { count: 3, sides: 0 },
+            Expression::Count(Comparison::EqualTo, 1, 2, 0),
+        ];
+
+        for expression in cases {
+            assert!(expression.plot_as::<Exact>().is_empty(), "{:?}", expression);
+        }
+    }
+
+    #[test]
+    fn plot_as_exact_produces_exact_odds() {
+        let expression = Expression::Sum(
+            Box::new(Expression::Dice {
+                count: 20,
+                sides: 6,
+            }),
+            Box::new(Expression::Choice(vec![
+                (1, Expression::Constant(0)),
+                (2, Expression::Die(4)),
+            ])),
+        );
+
+        let actual = expression.plot_as::<Exact>();
+
+        assert_eq!(actual.mass(), Exact::one());
+        // the only way to roll the minimum is all ones and a zero
+        assert_eq!(
+            actual.chance(20),
+            Exact::ratio(1, 3).times(&Exact::ratio(1, 6u64.pow(20)))
+        );
+    }
+
+    #[test]
+    fn plot_as_log_chance_keeps_extreme_tails() {
+        let expression = Expression::Dice {
+            count: 500,
+            sides: 6,
+        };
+
+        let actual = expression.plot_as::<LogChance>();
+
+        // 6^-500 is far too small for a float
+        assert_eq!(actual.chance(500).to_f64(), 0.0);
+        let expected = -500.0 * 6f64.ln();
+        assert!((actual.chance(500).ln() - expected).abs() < 1e-6);
+        assert!((actual.chance(3000).ln() - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn query_evaluates_to_scalar() {
+        let two_dice = Box::new(Expression::Dice { count: 2, sides: 6 });
+        let cases = vec![
+            (
+                Expression::Query(
+                    Query::Probability,
+                    Box::new(Expression::Compare(
+                        two_dice.clone(),
+                        Box::new(Expression::Constant(8)),
+                        Comparison::GreaterThanOrEqualTo,
+                    )),
+                ),
+                15.0 / 36.0,
+            ),
+            (
+                Expression::Query(
+                    Query::Expectation,
+                    Box::new(Expression::Sum(
+                        two_dice.clone(),
+                        Box::new(Expression::Constant(3)),
+                    )),
+                ),
+                10.0,
+            ),
+            (
+                Expression::Query(Query::Variance, two_dice.clone()),
+                35.0 / 6.0,
+            ),
+        ];
+
+        for (expression, expected) in cases {
+            let actual = expression.evaluate().expect("expression is a query");
+
+            assert!((expected - actual).abs() < 1e-9, "{:?}", expression);
+        }
+        assert_eq!(two_dice.evaluate(), Err(QueryError::NotAQuery));
+
+        // `let a = d4 in a | a > 10`
+        let impossible = Expression::Let(
+            String::from("a"),
+            Box::new(Expression::Die(4)),
+            Box::new(Expression::Given(
+                variable("a"),
+                Box::new(Expression::Compare(
+                    variable("a"),
+                    Box::new(Expression::Constant(10)),
+                    Comparison::GreaterThan,
+                )),
+            )),
+        );
+        for query in [Query::Probability, Query::Expectation, Query::Variance] {
+            let expression = Expression::Query(query, Box::new(impossible.clone()));
+            assert_eq!(expression.evaluate(), Err(QueryError::NoOutcomes));
+        }
+    }
+
+    fn variable(name: &str) -> Box<Expression> {
+        Box::new(Expression::Variable(String::from(name)))
+    }
+
+    #[test]
+    fn given_renormalizes_plot() {
+        // d20 given it's not a 1
+        let expression = Expression::Let(
+            String::from("a"),
+            Box::new(Expression::Die(20)),
+            Box::new(Expression::Given(
+                variable("a"),
+                Box::new(Expression::Compare(
+                    variable("a"),
+                    Box::new(Expression::Constant(1)),
+                    Comparison::GreaterThan,
+                )),
+            )),
+        );
+
+        let (actual, condition) = expression.plot_given::<Exact>();
+
+        assert_eq!(condition, Exact::ratio(19, 20));
+        assert_eq!(actual.min(), Some(2));
+        assert_eq!(actual.max(), Some(20));
+        assert!(actual
+            .chances()
+            .iter()
+            .all(|chance| *chance == Exact::ratio(1, 19)));
+    }
+
+    #[test]
+    fn given_shares_variables_with_condition() {
+        // only the even faces of a d6
+        let expression = Expression::Let(
+            String::from("a"),
+            Box::new(Expression::Die(6)),
+            Box::new(Expression::Given(
+                variable("a"),
+                Box::new(Expression::Compare(
+                    Box::new(Expression::Multiply(
+                        Box::new(Expression::Divide(
+                            variable("a"),
+                            Box::new(Expression::Constant(2)),
+                        )),
+                        Box::new(Expression::Constant(2)),
+                    )),
+                    variable("a"),
+                    Comparison::EqualTo,
+                )),
+            )),
+        );
+
+        let actual = expression.plot_as::<Exact>();
+
+        assert_eq!(
+            actual.to_table(),
+            [(2, 1.0 / 3.0), (4, 1.0 / 3.0), (6, 1.0 / 3.0)]
+                .iter()
+                .cloned()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn given_independent_damage() {
+        // damage given that the attack hit
+        let expression = Expression::Let(
+            String::from("attack"),
+            Box::new(Expression::Die(20)),
+            Box::new(Expression::Given(
+                Box::new(Expression::Dice { count: 2, sides: 6 }),
+                Box::new(Expression::Compare(
+                    Box::new(Expression::Sum(
+                        variable("attack"),
+                        Box::new(Expression::Constant(5)),
+                    )),
+                    Box::new(Expression::Constant(15)),
+                    Comparison::GreaterThanOrEqualTo,
+                )),
+            )),
+        );
+
+        let (actual, condition) = expression.plot_given::<Exact>();
+
+        assert_eq!(condition, Exact::ratio(11, 20));
+        assert_eq!(actual, Distribution::die(6).convolve_power(2));
+    }
+
+    #[test]
+    fn given_rolls_only_meet_condition() {
+        let expression = Expression::Let(
+            String::from("a"),
+            Box::new(Expression::Die(4)),
+            Box::new(Expression::Given(
+                variable("a"),
+                Box::new(Expression::Compare(
+                    variable("a"),
+                    Box::new(Expression::Constant(2)),
+                    Comparison::GreaterThan,
+                )),
+            )),
+        );
+
+        for _ in 0..100 {
+            let actual = expression.roll();
+            assert!(actual == 3 || actual == 4, "{}", actual);
+        }
+    }
+
+    #[test]
+    fn impossible_conditions_fail_to_roll() {
+        // a d4 is never above 10
+        let expression = Expression::Let(
+            String::from("a"),
+            Box::new(Expression::Die(4)),
+            Box::new(Expression::Given(
+                variable("a"),
+                Box::new(Expression::Compare(
+                    variable("a"),
+                    Box::new(Expression::Constant(10)),
+                    Comparison::GreaterThan,
+                )),
+            )),
+        );
+
+        assert_eq!(expression.try_roll(), Err(UnmetCondition));
+        assert!(expression.plot_as::<Exact>().is_empty());
+        assert!(Expression::Die(4).try_roll().is_ok());
+    }
+
+    #[test]
+    fn unconditional_plot_is_certain() {
+        let (_, condition) = Expression::Die(6).plot_given::<Exact>();
+
+        assert_eq!(condition, Exact::one());
+    }
+
+    #[test]
+    fn unbound_variables() {
+        let expression = Expression::Sum(
+            Box::new(Expression::Let(
+                String::from("a"),
+                variable("b"),
+                Box::new(Expression::Sum(variable("a"), variable("c"))),
+            )),
+            variable("a"),
+        );
+
+        assert_eq!(expression.unbound_variables(), vec!["b", "c", "a"]);
+    }
+
+    fn label(name: &str, expr: Expression) -> Box<Expression> {
+        Box::new(Expression::Label(String::from(name), Box::new(expr)))
+    }
+
+    /// `2d6[fire] + 1d8[slashing]`
+    fn typed_damage() -> Expression {
+        Expression::Sum(
+            label("fire", Expression::Dice { count: 2, sides: 6 }),
+            label("slashing", Expression::Die(8)),
+        )
+    }
+
+    #[test]
+    fn labels_do_not_change_outcome() {
+        let untyped = Expression::Sum(
+            Box::new(Expression::Dice { count: 2, sides: 6 }),
+            Box::new(Expression::Die(8)),
+        );
+
+        assert_eq!(typed_damage().plot_as::<Exact>(), untyped.plot_as());
+        assert_eq!(typed_damage().plot().total, 288.0);
+        assert_eq!(typed_damage().labels(), vec!["fire", "slashing"]);
+    }
+
+    #[test]
+    fn roll_labels_breaks_down_total() {
+        for _ in 0..100 {
+            let actual = typed_damage().roll_labels().unwrap();
+            let (fire, slashing) = (actual.labels[0].1, actual.labels[1].1);
+
+            assert_eq!(actual.labels[0].0, "fire");
+            assert!((2..=12).contains(&fire), "{}", fire);
+            assert!((1..=8).contains(&slashing), "{}", slashing);
+            assert_eq!(actual.total, fire + slashing);
+        }
+
+        let expression = Expression::Advantage(label("luck", Expression::Die(20)));
+        for _ in 0..100 {
+            let actual = expression.roll_labels().unwrap();
+            // only the higher die is kept, so only it is counted
+            assert_eq!(actual.labels[0].1, actual.total);
+        }
+    }
+
+    #[test]
+    fn labels_count_the_kept_roll() {
+        // `adv(2d6[fire])` and `dis(2d6[fire])`
+        let fire = || label("fire", Expression::Dice { count: 2, sides: 6 });
+        let mut rng = crate::random::seeded_rng(39);
+
+        for expression in [
+            Expression::Advantage(fire()),
+            Expression::Disadvantage(fire()),
+        ] {
+            for _ in 0..100 {
+                let actual = expression.roll_labels_with(&mut rng).unwrap();
+                assert_eq!(actual.labels, vec![(String::from("fire"), actual.total)]);
+            }
+            assert_eq!(
+                expression.plot_label_as::<Exact>("fire"),
+                expression.plot_as::<Exact>()
+            );
+        }
+    }
+
+    #[test]
+    fn nested_labels_count_once() {
+        // `(d6[fire])[fire] + d4[fire]`
+        let expression = Expression::Sum(
+            label("fire", *label("fire", Expression::Die(6))),
+            label("fire", Expression::Die(4)),
+        );
+
+        for _ in 0..100 {
+            let actual = expression.roll_labels().unwrap();
+            assert_eq!(actual.labels, vec![(String::from("fire"), actual.total)]);
+        }
+        assert_eq!(
+            expression.plot_label_as::<Exact>("fire"),
+            expression.plot_as::<Exact>()
+        );
+        assert_eq!(expression.plot_label("fire").max(), Some(10));
+        // the inner label is not halved again
+        assert_eq!(
+            expression.with_multiplier("fire", 1, 2).plot().plot,
+            crate::parse("d6 / 2 + d4 / 2").unwrap().plot().plot
+        );
+    }
+
+    #[test]
+    fn plot_label_of_each_type() {
+        let expression = typed_damage();
+
+        assert_eq!(
+            expression.plot_label_as::<Exact>("fire"),
+            Distribution::die(6).convolve_power(2)
+        );
+        assert_eq!(
+            expression.plot_label_as::<Exact>("slashing"),
+            Distribution::die(8)
+        );
+        assert_eq!(
+            expression.plot_label_as::<Exact>("cold"),
+            Distribution::constant(0)
+        );
+    }
+
+    #[test]
+    fn plot_label_given_condition() {
+        // fire damage only when the attack hits
+        let expression = Expression::Let(
+            String::from("attack"),
+            Box::new(Expression::Die(20)),
+            Box::new(Expression::Given(
+                label("fire", Expression::Die(6)),
+                Box::new(Expression::Compare(
+                    variable("attack"),
+                    Box::new(Expression::Constant(11)),
+                    Comparison::GreaterThanOrEqualTo,
+                )),
+            )),
+        );
+
+        assert_eq!(
+            expression.plot_label_as::<Exact>("fire"),
+            Distribution::die(6)
+        );
+    }
+
+    #[test]
+    fn plot_label_shares_rolls_with_variables() {
+        // the labelled roll is the one that has to be above 10
+        let expression = Expression::Let(
+            String::from("a"),
+            label("x", Expression::Die(20)),
+            Box::new(Expression::Given(
+                variable("a"),
+                Box::new(Expression::Compare(
+                    variable("a"),
+                    Box::new(Expression::Constant(10)),
+                    Comparison::GreaterThan,
+                )),
+            )),
+        );
+
+        let plot = expression.plot_label_as::<Exact>("x");
+
+        assert_eq!(plot.min(), Some(11));
+        assert_eq!(plot.max(), Some(20));
+        assert!(plot
+            .chances()
+            .iter()
+            .all(|chance| *chance == Exact::ratio(1, 10)));
+
+        let mut rng = crate::random::seeded_rng(4);
+        let rolls: Vec<RollResult> = (0..5000)
+            .map(|_| expression.roll_labels_with(&mut rng).unwrap().labels[0].1)
+            .collect();
+        let sampled = PlotResult {
+            total: 5000.0,
+            plot: Distribution::from_outcomes(rolls.iter().map(|roll| (*roll, 1.0))).normalize(),
+        };
+        let fit = crate::verification::goodness_of_fit(&sampled, &expression.plot_label("x"));
+        assert!(fit.fits(0.001), "{:?}", fit);
+    }
+
+    #[test]
+    fn multiplier_leaves_conditions() {
+        // halving fire damage doesn't change whether the condition is met
+        let condition = Expression::Compare(
+            label("fire", Expression::Die(6)),
+            Box::new(Expression::Constant(3)),
+            Comparison::GreaterThan,
+        );
+        let expression = Expression::Given(
+            label("fire", Expression::Die(6)),
+            Box::new(condition.clone()),
+        );
+
+        match expression.with_multiplier("fire", 1, 2) {
+            Expression::Given(_, actual) => assert_eq!(*actual, condition),
+            actual => panic!("{:?}", actual),
+        }
+    }
+
+    #[test]
+    fn multiplier_scales_label() {
+        // resistance halves fire damage, rounding down
+        let expression = typed_damage().with_multiplier("fire", 1, 2);
+
+        let fire = expression.plot_label_as::<Exact>("fire");
+
+        assert_eq!(fire.min(), Some(1));
+        assert_eq!(fire.max(), Some(6));
+        assert_eq!(fire.chance(1), Exact::ratio(3, 36));
+        assert_eq!(
+            expression.plot_label_as::<Exact>("slashing"),
+            Distribution::die(8)
+        );
+        assert_eq!(
+            expression.plot_as::<Exact>(),
+            fire.convolve(&Distribution::die(8))
+        );
+        for _ in 0..100 {
+            let actual = expression.roll_labels().unwrap();
+            assert!((1..=6).contains(&actual.labels[0].1));
+            assert_eq!(actual.total, actual.labels[0].1 + actual.labels[1].1);
+        }
+    }
+
+    #[test]
+    fn rollable_is_object_safe() {
+        let rollables: Vec<Box<dyn Rollable>> =
+            vec![Box::new(Expression::Die(6)), Box::new(typed_damage())];
+
+        let totals: Vec<f64> = rollables
+            .iter()
+            .map(|rollable| rollable.plot().total)
+            .collect();
+
+        assert_eq!(totals, vec![6.0, 288.0]);
+    }
+
+    #[test]
+    fn seeded_rolls_are_repeatable() {
+        let expression = Expression::Sum(
+            Box::new(Expression::Choice(vec![
+                (1, Expression::Dice { count: 3, sides: 6 }),
+                (1, Expression::Advantage(Box::new(Expression::Die(20)))),
+            ])),
+            Box::new(Expression::Count(Comparison::EqualTo, 6, 4, 6)),
+        );
+
+        let rolls = |seed| -> Vec<RollResult> {
+            let mut rng = crate::random::seeded_rng(seed);
+            (0..50).map(|_| expression.roll_with(&mut rng)).collect()
+        };
+
+        assert_eq!(rolls(7), rolls(7));
+        assert_ne!(rolls(7), rolls(8));
+        assert_eq!(expression.roll_seeded(7), rolls(7)[0]);
+        assert_eq!(
+            typed_damage().roll_labels_with(&mut crate::random::seeded_rng(3)),
+            typed_damage().roll_labels_with(&mut crate::random::seeded_rng(3))
+        );
+    }
 }
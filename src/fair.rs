@@ -0,0 +1,319 @@
+//! Fair Module
+//!
+//! Commit-reveal rolling for remote games, so that players can check that a
+//! roll wasn't fudged.
+//!
+//! 1. The game master picks a secret server seed and a nonce for the roll,
+//!    and publishes their `commitment` before anyone rolls.
+//! 2. Players supply their own entropy, such as a phrase typed into chat.
+//! 3. `roll_fair` parses the expression and mixes all three into the seed of
+//!    the roll, and records the revealed server seed alongside the trace of
+//!    the roll.
+//! 4. Anyone can `verify` the roll offline: the server seed and the nonce
+//!    must match the commitment, and rolling the same input again with the
+//!    same seed must give the same trace.
+//!
+//! The nonce is committed along with the server seed, so the game master
+//! can't try other nonces once the players' entropy is known. Every roll
+//! needs a new commitment, with a new nonce or server seed.
+//!
+//! The commitment is the SHA-256 hash of the length of the server seed, the
+//! server seed and the nonce, with the length and the nonce as 8 little
+//! endian bytes. The seed of a roll is the first 8 bytes, as a little endian
+//! number, of the SHA-256 hash of the length of the server seed, the server
+//! seed, the length of the player entropy, the player entropy and the nonce,
+//! with every length and the nonce as 8 little endian bytes. The roll then
+//! uses `random::seeded_rng`.
+
+use crate::expression::UnmetCondition;
+use crate::random::{seeded_rng, STREAM_VERSION};
+use crate::trace::Trace;
+use crate::ParseError;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// A roll made with a committed server seed, with everything needed to
+/// verify it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FairRoll {
+    /// the input of the expression that was rolled, exactly as it was given
+    /// to `roll_fair`
+    pub expression: String,
+    /// hex encoded SHA-256 hash of the server seed and the nonce, published
+    /// before the roll
+    pub commitment: String,
+    /// hex encoded server seed, revealed after the roll
+    pub server_seed: String,
+    /// entropy supplied by the players
+    pub player_entropy: String,
+    /// distinguishes several rolls made with the same server seed, and is
+    /// part of the commitment
+    pub nonce: u64,
+    /// the version of the stream of seeded rolls that was used
+    pub stream_version: u32,
+    pub trace: Trace,
+}
+
+/// Reasons that a fair roll couldn't be made
+#[derive(Clone, Debug, PartialEq)]
+pub enum RollError {
+    /// the expression could not be parsed
+    Parse(ParseError),
+    /// no roll met every condition of the expression
+    UnmetCondition,
+}
+
+impl fmt::Display for RollError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RollError::Parse(err) => write!(f, "{}", err),
+            RollError::UnmetCondition => write!(f, "{}", UnmetCondition),
+        }
+    }
+}
+
+impl std::error::Error for RollError {}
+
+impl From<ParseError> for RollError {
+    fn from(err: ParseError) -> RollError {
+        RollError::Parse(err)
+    }
+}
+
+impl From<UnmetCondition> for RollError {
+    fn from(_: UnmetCondition) -> RollError {
+        RollError::UnmetCondition
+    }
+}
+
+/// Reasons that a fair roll failed verification
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifyError {
+    /// the revealed server seed is not valid hex
+    InvalidSeed,
+    /// the revealed server seed or the nonce does not match the commitment
+    CommitmentMismatch,
+    /// the roll was made with a different version of the stream of seeded
+    /// rolls, so it can't be reproduced
+    StreamVersion(u32),
+    /// the expression could not be parsed
+    InvalidExpression,
+    /// rolling again did not give the same trace
+    TraceMismatch,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::InvalidSeed => write!(f, "server seed is not valid hex"),
+            VerifyError::CommitmentMismatch => {
+                write!(f, "server seed and nonce do not match the commitment")
+            }
+            VerifyError::StreamVersion(version) => write!(
+                f,
+                "roll used stream version {}, but this is version {}",
+                version, STREAM_VERSION
+            ),
+            VerifyError::InvalidExpression => write!(f, "expression could not be parsed"),
+            VerifyError::TraceMismatch => write!(f, "rolling again gave a different result"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let digit = |byte: u8| char::from(byte).to_digit(16).map(|digit| digit as u8);
+
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match *pair {
+            [high, low] => Some(digit(high)? << 4 | digit(low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+/// the hex encoded SHA-256 hash of a server seed and the nonce of a roll, to
+/// publish before rolling
+pub fn commitment(server_seed: &[u8], nonce: u64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update((server_seed.len() as u64).to_le_bytes());
+    hasher.update(server_seed);
+    hasher.update(nonce.to_le_bytes());
+    to_hex(&hasher.finalize())
+}
+
+/// the seed of a roll, mixed from the server seed, the player entropy and the
+/// nonce
+pub fn fair_seed(server_seed: &[u8], player_entropy: &str, nonce: u64) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update((server_seed.len() as u64).to_le_bytes());
+    hasher.update(server_seed);
+    hasher.update((player_entropy.len() as u64).to_le_bytes());
+    hasher.update(player_entropy.as_bytes());
+    hasher.update(nonce.to_le_bytes());
+    let hash = hasher.finalize();
+
+    let mut seed = [0; 8];
+    seed.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(seed)
+}
+
+/// Parse and roll an expression with a seed mixed from a committed server
+/// seed and nonce, and the players' entropy
+///
+/// the input is recorded as it was given, so that `verify` rolls exactly the
+/// same expression
+pub fn roll_fair(
+    input: &str,
+    server_seed: &[u8],
+    player_entropy: &str,
+    nonce: u64,
+) -> Result<FairRoll, RollError> {
+    let expression = crate::parse(input)?;
+    let seed = fair_seed(server_seed, player_entropy, nonce);
+
+    Ok(FairRoll {
+        expression: String::from(input),
+        commitment: commitment(server_seed, nonce),
+        server_seed: to_hex(server_seed),
+        player_entropy: String::from(player_entropy),
+        nonce,
+        stream_version: STREAM_VERSION,
+        trace: expression.roll_detailed_with(&mut seeded_rng(seed))?,
+    })
+}
+
+impl FairRoll {
+    /// Check that the server seed and the nonce match the commitment, and
+    /// that rolling again gives the same trace
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let server_seed = from_hex(&self.server_seed).ok_or(VerifyError::InvalidSeed)?;
+        if commitment(&server_seed, self.nonce) != self.commitment.to_lowercase() {
+            return Err(VerifyError::CommitmentMismatch);
+        }
+        if self.stream_version != STREAM_VERSION {
+            return Err(VerifyError::StreamVersion(self.stream_version));
+        }
+
+        let expression =
+            crate::parse(&self.expression).map_err(|_| VerifyError::InvalidExpression)?;
+        let seed = fair_seed(&server_seed, &self.player_entropy, self.nonce);
+        if expression
+            .roll_detailed_with(&mut seeded_rng(seed))
+            .as_ref()
+            != Ok(&self.trace)
+        {
+            return Err(VerifyError::TraceMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_SEED: &[u8] = b"the game master's secret";
+
+    fn fair_roll() -> FairRoll {
+        roll_fair(
+            "adv(d20) + 5 >= 15",
+            SERVER_SEED,
+            "rolling for initiative",
+            0,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn commitment_is_sha256() {
+        assert_eq!(
+            commitment(b"abc", 0),
+            "3e2f8940c3a0e0fd6f6e07bbf07f015dd7c147431e5fa14bf3f225d782641540"
+        );
+        assert_ne!(commitment(b"abc", 0), commitment(b"abc", 1));
+        assert_eq!(from_hex(&to_hex(SERVER_SEED)).unwrap(), SERVER_SEED);
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("+f"), None);
+        assert_eq!(from_hex("é"), None);
+    }
+
+    #[test]
+    fn seed_mixes_every_input() {
+        let seed = fair_seed(SERVER_SEED, "entropy", 0);
+
+        assert_eq!(seed, fair_seed(SERVER_SEED, "entropy", 0));
+        assert_ne!(seed, fair_seed(b"another secret", "entropy", 0));
+        assert_ne!(seed, fair_seed(SERVER_SEED, "more entropy", 0));
+        assert_ne!(seed, fair_seed(SERVER_SEED, "entropy", 1));
+        // lengths keep the boundary between the inputs
+        assert_ne!(fair_seed(b"ab", "c", 0), fair_seed(b"a", "bc", 0));
+    }
+
+    #[test]
+    fn fair_roll_verifies() {
+        let roll = fair_roll();
+
+        assert_eq!(roll.commitment, commitment(SERVER_SEED, 0));
+        assert_eq!(roll.expression, "adv(d20) + 5 >= 15");
+        assert_eq!(roll.verify(), Ok(()));
+    }
+
+    #[test]
+    fn input_is_rolled_as_given() {
+        // the input is kept, rather than the display of its expression
+        let roll = roll_fair(" 1d6 +  (2) ", SERVER_SEED, "entropy", 0).unwrap();
+        assert_eq!(roll.expression, " 1d6 +  (2) ");
+        assert_eq!(roll.verify(), Ok(()));
+
+        assert!(matches!(
+            roll_fair("d20 | d20 > 1", SERVER_SEED, "entropy", 0),
+            Err(RollError::Parse(_))
+        ));
+        assert_eq!(
+            roll_fair("let a = d4 in a | a > 4", SERVER_SEED, "entropy", 0),
+            Err(RollError::UnmetCondition)
+        );
+    }
+
+    #[test]
+    fn fudged_rolls_fail_verification() {
+        let mut fudged = fair_roll();
+        fudged.trace.value = 1 - fudged.trace.value;
+        assert_eq!(fudged.verify(), Err(VerifyError::TraceMismatch));
+
+        let mut swapped = fair_roll();
+        swapped.server_seed = to_hex(b"a more convenient secret");
+        assert_eq!(swapped.verify(), Err(VerifyError::CommitmentMismatch));
+
+        // the nonce can't be changed after the players' entropy is known
+        let mut retried = fair_roll();
+        retried.nonce = 1;
+        assert_eq!(retried.verify(), Err(VerifyError::CommitmentMismatch));
+        let mut regrind = roll_fair(
+            &retried.expression,
+            SERVER_SEED,
+            "rolling for initiative",
+            1,
+        )
+        .unwrap();
+        regrind.commitment = fair_roll().commitment;
+        assert_eq!(regrind.verify(), Err(VerifyError::CommitmentMismatch));
+
+        let mut garbled = fair_roll();
+        garbled.server_seed = String::from("not hex");
+        assert_eq!(garbled.verify(), Err(VerifyError::InvalidSeed));
+
+        let mut outdated = fair_roll();
+        outdated.stream_version = 0;
+        assert_eq!(outdated.verify(), Err(VerifyError::StreamVersion(0)));
+    }
+}
@@ -0,0 +1,380 @@
+//! Joint Module
+//!
+//! Plot several expressions over the same random variables, to see how they
+//! vary together, e.g. whether an attack hits and how much damage it deals.
+
+use crate::distribution::Distribution;
+use crate::expression::{Expression, Scope};
+use crate::traits::{Chance, RollResult};
+use crate::weight::Weight;
+use std::collections::BTreeMap;
+
+/// Likelihood of every combination of outcomes of several named expressions
+#[derive(Clone, Debug, PartialEq)]
+pub struct JointDistribution<W: Weight = Chance> {
+    /// names of the expressions, in the order of the outcomes
+    names: Vec<String>,
+    /// likelihood of each combination of outcomes
+    table: BTreeMap<Vec<RollResult>, W>,
+}
+
+/// Plot the joint distribution of several expressions that share variables
+///
+/// `bindings` bind the outcome of an expression to a name, just like `let`.
+/// Each binding can use the variables bound before it, and every output can
+/// use every binding. Dice that are not bound to a variable are rolled
+/// separately for each output.
+pub fn plot_joint(
+    bindings: &[(&str, Expression)],
+    outputs: &[(&str, Expression)],
+) -> JointDistribution<Chance> {
+    plot_joint_as(bindings, outputs)
+}
+
+/// Plot the joint distribution of several expressions that share variables,
+/// with their likelihood represented by any kind of `Weight`
+pub fn plot_joint_as<W: Weight>(
+    bindings: &[(&str, Expression)],
+    outputs: &[(&str, Expression)],
+) -> JointDistribution<W> {
+    let mut table = BTreeMap::new();
+    add_outcomes(bindings, outputs, &Scope::new(), W::one(), &mut table);
+
+    let joint = JointDistribution {
+        names: outputs
+            .iter()
+            .map(|(name, _)| String::from(*name))
+            .collect(),
+        table,
+    };
+
+    let conditional = bindings
+        .iter()
+        .chain(outputs.iter())
+        .any(|(_, expression)| expression.is_conditional());
+    if conditional {
+        joint.normalize()
+    } else {
+        joint
+    }
+}
+
+/// bind the remaining variables to every possible outcome, and then add
+/// every combination of outcomes of the outputs to the table
+fn add_outcomes<W: Weight>(
+    bindings: &[(&str, Expression)],
+    outputs: &[(&str, Expression)],
+    scope: &Scope,
+    chance: W,
+    table: &mut BTreeMap<Vec<RollResult>, W>,
+) {
+    if let Some(((name, value), bindings)) = bindings.split_first() {
+        for (value, value_chance) in value.plot_in::<W>(scope).iter() {
+            if value_chance.is_zero() {
+                continue;
+            }
+            let mut scope = scope.clone();
+            scope.insert(String::from(*name), value);
+            add_outcomes(
+                bindings,
+                outputs,
+                &scope,
+                chance.times(&value_chance),
+                table,
+            );
+        }
+        return;
+    }
+
+    add_outputs(outputs, scope, chance, table);
+}
+
+/// add every combination of outcomes of the outputs to the table, with
+/// variables bound to the given outcomes
+pub(crate) fn add_outputs<W: Weight, S: AsRef<str>>(
+    outputs: &[(S, Expression)],
+    scope: &Scope,
+    chance: W,
+    table: &mut BTreeMap<Vec<RollResult>, W>,
+) {
+    // with every variable bound, the outputs are independent
+    let mut combinations: Vec<(Vec<RollResult>, W)> = vec![(vec![], chance)];
+    for (_, output) in outputs {
+        let plot = output.plot_in::<W>(scope);
+        combinations = combinations
+            .iter()
+            .flat_map(|(outcome, chance)| {
+                plot.iter()
+                    .filter(|(_, output_chance)| !output_chance.is_zero())
+                    .map(move |(value, output_chance)| {
+                        let mut outcome = outcome.clone();
+                        outcome.push(value);
+                        (outcome, chance.times(&output_chance))
+                    })
+            })
+            .collect();
+    }
+
+    for (outcome, chance) in combinations {
+        let total = table.entry(outcome).or_insert_with(W::zero);
+        *total = total.plus(&chance);
+    }
+}
+
+impl<W: Weight> JointDistribution<W> {
+    /// Create a joint distribution from a list of combinations of outcomes
+    /// and their likelihood, combining the likelihood of repeated
+    /// combinations
+    ///
+    /// Every combination has one outcome for each name, in the same order.
+    pub fn from_outcomes<I>(names: Vec<String>, outcomes: I) -> JointDistribution<W>
+    where
+        I: IntoIterator<Item = (Vec<RollResult>, W)>,
+    {
+        let mut table = BTreeMap::new();
+        for (outcome, chance) in outcomes {
+            assert_eq!(
+                outcome.len(),
+                names.len(),
+                "every combination needs an outcome for each name"
+            );
+            let total = table.entry(outcome).or_insert_with(W::zero);
+            *total = total.plus(&chance);
+        }
+
+        JointDistribution { names, table }
+    }
+
+    /// the names of the expressions, in the order of the outcomes
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// the position of an expression in each combination of outcomes
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|other| other == name)
+    }
+
+    fn expect_index(&self, name: &str) -> usize {
+        self.index(name)
+            .unwrap_or_else(|| panic!("unknown expression `{}`", name))
+    }
+
+    /// Iterate over every possible combination of outcomes, in order, along
+    /// with its likelihood
+    pub fn iter(&self) -> impl Iterator<Item = (&[RollResult], &W)> {
+        self.table
+            .iter()
+            .map(|(outcome, chance)| (outcome.as_slice(), chance))
+    }
+
+    /// the likelihood of a single combination of outcomes
+    pub fn chance(&self, outcome: &[RollResult]) -> W {
+        self.table.get(outcome).cloned().unwrap_or_else(W::zero)
+    }
+
+    /// the likelihood of every combination of outcomes that matches a
+    /// predicate
+    pub fn chance_where<F>(&self, predicate: F) -> W
+    where
+        F: Fn(&[RollResult]) -> bool,
+    {
+        self.iter()
+            .filter(|(outcome, _)| predicate(outcome))
+            .fold(W::zero(), |total, (_, chance)| total.plus(chance))
+    }
+
+    /// Scale the likelihood of every combination of outcomes so that they
+    /// add up to a certainty
+    pub fn normalize(&self) -> JointDistribution<W> {
+        let mass = self
+            .table
+            .values()
+            .fold(W::zero(), |total, chance| total.plus(chance));
+
+        JointDistribution {
+            names: self.names.clone(),
+            table: self
+                .table
+                .iter()
+                .map(|(outcome, chance)| (outcome.clone(), chance.divide(&mass)))
+                .collect(),
+        }
+    }
+
+    /// the distribution of a single expression, regardless of the others
+    pub fn marginal(&self, name: &str) -> Distribution<W> {
+        let index = self.expect_index(name);
+
+        Distribution::from_outcomes(
+            self.iter()
+                .map(|(outcome, chance)| (outcome[index], chance.clone())),
+        )
+    }
+
+    /// the distribution of a single expression, given that another
+    /// expression had a certain outcome
+    pub fn conditional(&self, name: &str, given: &str, value: RollResult) -> Distribution<W> {
+        let index = self.expect_index(name);
+        let given = self.expect_index(given);
+
+        let plot: Distribution<W> = Distribution::from_outcomes(
+            self.iter()
+                .filter(|(outcome, _)| outcome[given] == value)
+                .map(|(outcome, chance)| (outcome[index], chance.clone())),
+        );
+        if plot.is_empty() {
+            return plot;
+        }
+
+        plot.normalize()
+    }
+}
+
+impl JointDistribution<Chance> {
+    /// the expected value of the product of the distances between the
+    /// outcomes of two expressions and their means
+    pub fn covariance(&self, left: &str, right: &str) -> f64 {
+        let left_mean = self.marginal(left).mean();
+        let right_mean = self.marginal(right).mean();
+        let left = self.expect_index(left);
+        let right = self.expect_index(right);
+
+        self.iter()
+            .map(|(outcome, chance)| {
+                (f64::from(outcome[left]) - left_mean)
+                    * (f64::from(outcome[right]) - right_mean)
+                    * chance
+            })
+            .sum()
+    }
+
+    /// the covariance of two expressions scaled to between -1 and 1
+    pub fn correlation(&self, left: &str, right: &str) -> f64 {
+        self.covariance(left, right)
+            / (self.marginal(left).standard_deviation() * self.marginal(right).standard_deviation())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::Comparison;
+    use crate::test_helpers::assert_close;
+    use crate::weight::Exact;
+
+    fn variable(name: &str) -> Box<Expression> {
+        Box::new(Expression::Variable(String::from(name)))
+    }
+
+    type Named = Vec<(&'static str, Expression)>;
+
+    /// whether an attack hits, and the damage that it deals
+    fn attack() -> (Named, Named) {
+        let hit = Expression::Compare(
+            variable("attack"),
+            Box::new(Expression::Constant(11)),
+            Comparison::GreaterThanOrEqualTo,
+        );
+        let damage = Expression::Multiply(Box::new(hit.clone()), Box::new(Expression::Die(8)));
+
+        (
+            vec![("attack", Expression::Die(20))],
+            vec![("hit", hit), ("damage", damage)],
+        )
+    }
+
+    #[test]
+    fn joint_shares_bindings() {
+        let (bindings, outputs) = attack();
+
+        let joint = plot_joint_as::<Exact>(&bindings, &outputs);
+
+        assert_eq!(joint.names(), &["hit", "damage"]);
+        assert_eq!(joint.chance(&[0, 0]), Exact::ratio(1, 2));
+        assert_eq!(joint.chance(&[1, 3]), Exact::ratio(1, 16));
+        // damage can't be dealt without hitting
+        assert_eq!(joint.chance(&[0, 3]), Exact::zero());
+        assert_eq!(
+            joint.chance_where(|outcome| outcome[1] >= 7),
+            Exact::ratio(1, 8)
+        );
+    }
+
+    #[test]
+    fn marginal_and_conditional() {
+        let (bindings, outputs) = attack();
+
+        let joint = plot_joint_as::<Exact>(&bindings, &outputs);
+
+        assert_eq!(
+            joint.marginal("hit"),
+            Distribution::new(0, vec![Exact::ratio(1, 2), Exact::ratio(1, 2)])
+        );
+        assert_eq!(joint.conditional("damage", "hit", 1), Distribution::die(8));
+        assert_eq!(
+            joint.conditional("damage", "hit", 0),
+            Distribution::constant(0)
+        );
+        assert!(joint.conditional("damage", "hit", 2).is_empty());
+    }
+
+    #[test]
+    fn covariance_and_correlation() {
+        let (bindings, outputs) = attack();
+
+        let joint = plot_joint(&bindings, &outputs);
+
+        // E[hit * damage] - E[hit] * E[damage]
+        assert_close(2.25 - 0.5 * 2.25, joint.covariance("hit", "damage"));
+        assert_close(0.25, joint.covariance("hit", "hit"));
+        let damage_variance: f64 = 0.5 * 25.5 - 2.25 * 2.25;
+        assert_close(
+            1.125 / (0.5 * damage_variance.sqrt()),
+            joint.correlation("hit", "damage"),
+        );
+    }
+
+    #[test]
+    fn unbound_dice_are_independent() {
+        let joint = plot_joint(&[], &[("a", Expression::Die(6)), ("b", Expression::Die(6))]);
+
+        assert_eq!(joint.iter().count(), 36);
+        assert_close(0.0, joint.covariance("a", "b"));
+    }
+
+    #[test]
+    fn conditions_are_renormalized() {
+        // d20 given it's not a 1, alongside whether it's a critical hit
+        let bindings = vec![(
+            "attack",
+            Expression::Given(
+                Box::new(Expression::Die(20)),
+                Box::new(Expression::Constant(1)),
+            ),
+        )];
+        let outputs = vec![
+            ("attack", *variable("attack")),
+            (
+                "critical",
+                Expression::Given(
+                    Box::new(Expression::Compare(
+                        variable("attack"),
+                        Box::new(Expression::Constant(20)),
+                        Comparison::EqualTo,
+                    )),
+                    Box::new(Expression::Compare(
+                        variable("attack"),
+                        Box::new(Expression::Constant(1)),
+                        Comparison::GreaterThan,
+                    )),
+                ),
+            ),
+        ];
+
+        let joint = plot_joint_as::<Exact>(&bindings, &outputs);
+
+        assert_eq!(joint.marginal("attack").min(), Some(2));
+        assert_eq!(joint.marginal("critical").chance(1), Exact::ratio(1, 19));
+    }
+}
@@ -4,10 +4,25 @@ extern crate nom;
 #[macro_use]
 extern crate serde_derive;
 
+pub mod alias;
+pub mod compare;
+pub mod distribution;
 pub mod expression;
+pub mod fair;
+pub mod joint;
+pub mod narrative;
 pub mod operators;
+pub mod program;
+pub mod random;
+pub mod record;
+pub mod simulate;
+pub mod statistics;
+pub mod trace;
 pub mod traits;
+pub mod verification;
+pub mod weight;
 
 mod parser;
+mod test_helpers;
 
-pub use parser::parse;
+pub use parser::{parse, ParseError};
@@ -0,0 +1,294 @@
+//! Narrative Module
+//!
+//! The narrative dice of Genesys and Star Wars, whose faces show symbols that
+//! cancel against each other: each failure cancels a success, and each
+//! threat cancels an advantage. A triumph also counts as a success and a
+//! despair also counts as a failure, but they are never cancelled.
+
+use crate::expression::Expression;
+use crate::joint::JointDistribution;
+use crate::record::RecordExpression;
+use crate::traits::{Chance, RollResult};
+use rand::Rng;
+
+/// the symbols on the faces of narrative dice, in the order of each face
+pub const SYMBOLS: [&str; 6] = [
+    "success",
+    "failure",
+    "advantage",
+    "threat",
+    "triumph",
+    "despair",
+];
+
+/// the components of a narrative roll once symbols have cancelled, in the
+/// order of its outcomes
+pub const NET_SYMBOLS: [&str; 4] = ["success", "advantage", "triumph", "despair"];
+
+const BLANK: [RollResult; 6] = [0, 0, 0, 0, 0, 0];
+const S: [RollResult; 6] = [1, 0, 0, 0, 0, 0];
+const SS: [RollResult; 6] = [2, 0, 0, 0, 0, 0];
+const SA: [RollResult; 6] = [1, 0, 1, 0, 0, 0];
+const A: [RollResult; 6] = [0, 0, 1, 0, 0, 0];
+const AA: [RollResult; 6] = [0, 0, 2, 0, 0, 0];
+const F: [RollResult; 6] = [0, 1, 0, 0, 0, 0];
+const FF: [RollResult; 6] = [0, 2, 0, 0, 0, 0];
+const FT: [RollResult; 6] = [0, 1, 0, 1, 0, 0];
+const T: [RollResult; 6] = [0, 0, 0, 1, 0, 0];
+const TT: [RollResult; 6] = [0, 0, 0, 2, 0, 0];
+const TRIUMPH: [RollResult; 6] = [0, 0, 0, 0, 1, 0];
+const DESPAIR: [RollResult; 6] = [0, 0, 0, 0, 0, 1];
+
+/// A narrative die
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NarrativeDie {
+    /// blue d6, added for helpful circumstances
+    Boost,
+    /// black d6, added for hindering circumstances
+    Setback,
+    /// green d8, for a character's raw ability
+    Ability,
+    /// purple d8, for the difficulty of a task
+    Difficulty,
+    /// yellow d12, for a character's trained ability
+    Proficiency,
+    /// red d12, for the most difficult tasks
+    Challenge,
+}
+
+use NarrativeDie::*;
+
+impl NarrativeDie {
+    /// the symbols on every face of this die, counted in the order of
+    /// `SYMBOLS`
+    pub fn faces(self) -> &'static [[RollResult; 6]] {
+        match self {
+            Boost => &[BLANK, BLANK, S, SA, AA, A],
+            Setback => &[BLANK, BLANK, F, F, T, T],
+            Ability => &[BLANK, S, S, SS, A, A, SA, AA],
+            Difficulty => &[BLANK, F, FF, T, T, T, TT, FT],
+            Proficiency => &[BLANK, S, S, SS, SS, A, SA, SA, SA, AA, AA, TRIUMPH],
+            Challenge => &[BLANK, F, F, FF, FF, T, T, FT, FT, TT, TT, DESPAIR],
+        }
+    }
+
+    /// Create a record expression for a single roll of this die, with a
+    /// component for each symbol before cancelling
+    pub fn expression(self) -> RecordExpression {
+        let faces: Vec<&[RollResult]> = self.faces().iter().map(|face| &face[..]).collect();
+        RecordExpression::die(&SYMBOLS, &faces).expect("every face has a count of each symbol")
+    }
+}
+
+/// The symbols left over from a narrative roll once they have cancelled
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NarrativeResult {
+    /// successes left over, or failures if negative
+    pub success: RollResult,
+    /// advantages left over, or threats if negative
+    pub advantage: RollResult,
+    pub triumph: RollResult,
+    pub despair: RollResult,
+}
+
+impl NarrativeResult {
+    /// Read a result from an outcome with a value for each of `NET_SYMBOLS`
+    pub fn from_outcome(outcome: &[RollResult]) -> NarrativeResult {
+        NarrativeResult {
+            success: outcome[0],
+            advantage: outcome[1],
+            triumph: outcome[2],
+            despair: outcome[3],
+        }
+    }
+
+    /// whether the check succeeded, with at least one success left over
+    pub fn is_success(&self) -> bool {
+        self.success > 0
+    }
+}
+
+/// A pool of narrative dice, as the number of each kind of die
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NarrativePool(pub Vec<(u32, NarrativeDie)>);
+
+fn variable(name: &str) -> Box<Expression> {
+    Box::new(Expression::Variable(String::from(name)))
+}
+
+impl NarrativePool {
+    /// Create a record expression for a roll of this pool, with a component
+    /// for each of `NET_SYMBOLS`
+    pub fn expression(&self) -> RecordExpression {
+        let blank = RecordExpression::die(&SYMBOLS, &[&BLANK]).expect("blank has every symbol");
+        let symbols = self.0.iter().fold(blank, |pool, (count, die)| {
+            RecordExpression::Sum(
+                Box::new(pool),
+                Box::new(RecordExpression::Repeat(*count, Box::new(die.expression()))),
+            )
+        });
+
+        let success = Expression::Diff(
+            Box::new(Expression::Sum(variable("success"), variable("triumph"))),
+            Box::new(Expression::Sum(variable("failure"), variable("despair"))),
+        );
+        let advantage = Expression::Diff(variable("advantage"), variable("threat"));
+
+        RecordExpression::map(
+            symbols,
+            vec![
+                (String::from("success"), success),
+                (String::from("advantage"), advantage),
+                (String::from("triumph"), *variable("triumph")),
+                (String::from("despair"), *variable("despair")),
+            ],
+        )
+        .expect("every symbol is a component of the pool")
+    }
+
+    /// Get the symbols left over from a single roll of this pool
+    pub fn roll(&self) -> NarrativeResult {
+        self.roll_with(&mut rand::thread_rng())
+    }
+
+    /// Get the symbols left over from a single roll of this pool, using the
+    /// given random number generator
+    pub fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> NarrativeResult {
+        let outcome = self
+            .expression()
+            .roll_with(rng)
+            .expect("narrative pools have no conditions");
+        NarrativeResult::from_outcome(&outcome)
+    }
+
+    /// Create a joint distribution of the symbols left over from every
+    /// possible roll of this pool
+    pub fn plot(&self) -> JointDistribution<Chance> {
+        self.expression().plot()
+    }
+
+    /// the likelihood that the symbols left over match a predicate, e.g. a
+    /// success with at least one advantage
+    pub fn chance_where<F>(&self, predicate: F) -> Chance
+    where
+        F: Fn(&NarrativeResult) -> bool,
+    {
+        self.plot()
+            .chance_where(|outcome| predicate(&NarrativeResult::from_outcome(outcome)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::assert_close;
+    use crate::weight::{Exact, Weight};
+
+    #[test]
+    fn dice_have_the_right_faces() {
+        let sides: Vec<usize> = [Boost, Setback, Ability, Difficulty, Proficiency, Challenge]
+            .iter()
+            .map(|die| die.faces().len())
+            .collect();
+
+        assert_eq!(sides, vec![6, 6, 8, 8, 12, 12]);
+        let total = |die: NarrativeDie, symbol: usize| -> RollResult {
+            die.faces().iter().map(|face| face[symbol]).sum()
+        };
+        assert_eq!((total(Ability, 0), total(Ability, 2)), (5, 5));
+        assert_eq!((total(Difficulty, 1), total(Difficulty, 3)), (4, 6));
+        assert_eq!((total(Proficiency, 0), total(Proficiency, 2)), (9, 8));
+        assert_eq!((total(Challenge, 1), total(Challenge, 3)), (8, 8));
+    }
+
+    #[test]
+    fn symbols_cancel() {
+        // one ability die against one difficulty die
+        let pool = NarrativePool(vec![(1, Ability), (1, Difficulty)]);
+
+        let plot = pool.expression().plot_as::<Exact>();
+
+        assert_eq!(plot.names(), &NET_SYMBOLS);
+        // every symbol cancelled, including two blanks
+        assert_eq!(plot.chance(&[0, 0, 0, 0]), Exact::ratio(12, 64));
+        assert_eq!(
+            plot.chance_where(|outcome| outcome[0] > 0),
+            Exact::ratio(22, 64)
+        );
+    }
+
+    #[test]
+    fn triumph_counts_as_a_success() {
+        let pool = NarrativePool(vec![(1, Proficiency), (1, Challenge)]);
+
+        let plot = pool.expression().plot_as::<Exact>();
+
+        // triumph and despair both show, and cancel as success and failure
+        assert_eq!(plot.chance(&[0, 0, 1, 1]), Exact::ratio(1, 144));
+        // triumph on a blank
+        assert_eq!(plot.chance(&[1, 0, 1, 0]), Exact::ratio(1, 144));
+        assert_eq!(plot.marginal("triumph").chance(1), Exact::ratio(1, 12));
+    }
+
+    #[test]
+    fn chance_of_success_with_advantage() {
+        let pool = NarrativePool(vec![(2, Ability), (1, Proficiency), (2, Difficulty)]);
+
+        let plot = pool.expression().plot_as::<Exact>();
+
+        // out of the 8 * 8 * 12 * 8 * 8 = 49152 rolls
+        assert_eq!(
+            plot.chance_where(|outcome| outcome[0] > 0),
+            Exact::ratio(31988, 49152)
+        );
+        assert_eq!(
+            plot.chance_where(|outcome| outcome[0] > 0 && outcome[1] > 0),
+            Exact::ratio(10542, 49152)
+        );
+        assert_eq!(plot.chance(&[0, 0, 0, 0]), Exact::ratio(1924, 49152));
+        assert_close(
+            31988.0 / 49152.0,
+            pool.chance_where(NarrativeResult::is_success),
+        );
+        assert_close(
+            10542.0 / 49152.0,
+            pool.chance_where(|result| result.is_success() && result.advantage > 0),
+        );
+    }
+
+    #[test]
+    fn empty_pool_is_blank() {
+        let pool = NarrativePool::default();
+
+        assert_eq!(
+            pool.roll(),
+            NarrativeResult {
+                success: 0,
+                advantage: 0,
+                triumph: 0,
+                despair: 0
+            }
+        );
+        assert_eq!(
+            pool.expression().plot_as::<Exact>().chance(&[0; 4]),
+            Exact::one()
+        );
+    }
+
+    #[test]
+    fn roll_matches_plot() {
+        let pool = NarrativePool(vec![(1, Boost), (1, Setback), (2, Challenge)]);
+        let plot = pool.plot();
+
+        for _ in 0..500 {
+            let result = pool.roll();
+            let outcome = [
+                result.success,
+                result.advantage,
+                result.triumph,
+                result.despair,
+            ];
+            assert!(plot.chance(&outcome) > 0.0, "{:?}", result);
+        }
+    }
+}
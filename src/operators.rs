@@ -58,7 +58,7 @@ pub fn compare(left: &RollResult, right: &RollResult) -> RollResult {
     }
 }
 
-/// 1 if right is greater than left else 0
+/// 1 if left is greater than right else 0
 pub fn greater_than(left: &RollResult, right: &RollResult) -> RollResult {
     if *left > *right {
         1
@@ -67,7 +67,7 @@ pub fn greater_than(left: &RollResult, right: &RollResult) -> RollResult {
     }
 }
 
-/// 1 if right is greater than or equal to left else 0
+/// 1 if left is greater than or equal to right else 0
 pub fn greater_than_or_equal_to(left: &RollResult, right: &RollResult) -> RollResult {
     if *left >= *right {
         1
@@ -76,25 +76,25 @@ pub fn greater_than_or_equal_to(left: &RollResult, right: &RollResult) -> RollRe
     }
 }
 
-/// 1 if right is less than left else 0
+/// 1 if left is less than right else 0
 pub fn less_than(left: &RollResult, right: &RollResult) -> RollResult {
-    if *left > *right {
+    if *left < *right {
         1
     } else {
         0
     }
 }
 
-/// 1 if right is less than left else 0
+/// 1 if left is less than or equal to right else 0
 pub fn less_than_or_equal_to(left: &RollResult, right: &RollResult) -> RollResult {
-    if *left >= *right {
+    if *left <= *right {
         1
     } else {
         0
     }
 }
 
-/// 1 if right is equal to left else 0
+/// 1 if left is equal to right else 0
 pub fn equal_to(left: &RollResult, right: &RollResult) -> RollResult {
     if *left == *right {
         1
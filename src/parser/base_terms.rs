@@ -1,11 +1,21 @@
-use nom;
 use nom::digit;
 use nom::types::CompleteStr;
 
 use std::str::FromStr;
 
 use crate::expression::Expression;
-use Expression::{Constant, Die, Sum};
+use Expression::{Constant, Dice, Die, Variable};
+
+/// words that can't be used as variable names
+const KEYWORDS: &[&str] = &["adv", "choose", "count", "dis", "in", "let"];
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_lowercase() || c == '_'
+}
+
+pub fn is_identifier_char(c: char) -> bool {
+    is_identifier_start(c) || c.is_ascii_digit()
+}
 
 named!(
     parse_eval_signs<CompleteStr, char>,
@@ -38,8 +48,8 @@ named!(
         ),
         |(sign, value): (Option<char>, u32)| {
             match sign {
-                Some('-') => -1 * value as i32,
-                _ => 1 * value as i32
+                Some('-') => -(value as i32),
+                _ => value as i32
             }
         }
     )
@@ -65,23 +75,62 @@ named!(
     parse_die_coefficient<CompleteStr, Expression>,
     map!(
         do_parse!(
-            coefficient: ws!(parse_unsigned_number) >>
-                         tag!("d")                  >>
-            num:         parse_unsigned_number      >>
-            (coefficient, num)
+            count: ws!(parse_unsigned_number) >>
+                   tag!("d")                   >>
+            sides: parse_unsigned_number       >>
+            (count, sides)
+        ),
+        |(count, sides)| {
+            match count {
+                0 => Constant(0),
+                1 => Die(sides),
+                _ => Dice { count, sides },
+            }
+        }
+    )
+);
+
+named!(
+    pub parse_identifier<CompleteStr, String>,
+    map_res!(
+        recognize!(
+            pair!(
+                take_while1!(is_identifier_start),
+                take_while!(is_identifier_char)
+            )
         ),
-        |(coefficient, num)| {
-            if coefficient == 0 {
-                return Constant(0)
+        |CompleteStr(name): CompleteStr| -> Result<String, &str> {
+            if KEYWORDS.contains(&name) {
+                return Err("keywords can't be used as variable names");
+            }
+            // names like `d20` would be parsed as dice
+            let mut chars = name.chars();
+            if chars.next() == Some('d') && chars.next().is_some_and(|c| c.is_ascii_digit()) {
+                return Err("variable names can't look like dice");
             }
 
-            (0..coefficient-1).fold(Die(num), |acc, _| {
-                Sum(Box::new(acc), Box::new(Die(num)))
-            })
+            Ok(String::from(name))
         }
     )
 );
 
+named!(
+    pub parse_variable<CompleteStr, Expression>,
+    ws!(
+        map!(parse_identifier, Variable)
+    )
+);
+
+named!(
+    pub parse_pool<CompleteStr, (u32, u32)>,
+    do_parse!(
+        count: ws!(opt!(parse_unsigned_number)) >>
+               tag!("d")                        >>
+        sides: parse_unsigned_number            >>
+        ((count.unwrap_or(1), sides))
+    )
+);
+
 named!(
     pub parse_die<CompleteStr, Expression>,
     alt_complete!(parse_die_single | parse_die_coefficient)
@@ -132,35 +181,48 @@ mod tests {
             (" d10", Die(10)),
             ("\nd12", Die(12)),
             ("\td20  ", Die(20)),
-            (" 2d6 ", Sum(Box::new(Die(6)), Box::new(Die(6)))),
-            (
-                "4d6",
-                Sum(
-                    Box::new(Sum(
-                        Box::new(Sum(Box::new(Die(6)), Box::new(Die(6)))),
-                        Box::new(Die(6)),
-                    )),
-                    Box::new(Die(6)),
-                ),
-            ),
+            (" 2d6 ", Dice { count: 2, sides: 6 }),
+            ("4d6", Dice { count: 4, sides: 6 }),
+            ("1d8", Die(8)),
+            ("0d8", Constant(0)),
             (
-                "6d6",
-                Sum(
-                    Box::new(Sum(
-                        Box::new(Sum(
-                            Box::new(Sum(
-                                Box::new(Sum(Box::new(Die(6)), Box::new(Die(6)))),
-                                Box::new(Die(6)),
-                            )),
-                            Box::new(Die(6)),
-                        )),
-                        Box::new(Die(6)),
-                    )),
-                    Box::new(Die(6)),
-                ),
+                "100d6",
+                Dice {
+                    count: 100,
+                    sides: 6,
+                },
             ),
         ];
 
         test_parser(parse_die, cases);
     }
+
+    #[test]
+    fn test_parse_variable() {
+        let cases = vec![
+            ("a", Variable(String::from("a"))),
+            (" attack ", Variable(String::from("attack"))),
+            ("_x1", Variable(String::from("_x1"))),
+            ("dmg", Variable(String::from("dmg"))),
+            ("letter", Variable(String::from("letter"))),
+        ];
+
+        test_parser(parse_variable, cases);
+
+        for keyword in &["let", "in", "count", "d20", "d4x"] {
+            assert!(parse_variable((*keyword).into()).is_err(), "{}", keyword);
+        }
+    }
+
+    #[test]
+    fn test_parse_pool() {
+        let cases = vec![
+            ("d6", (1, 6)),
+            (" 5d6 ", (5, 6)),
+            ("\t10d10", (10, 10)),
+            ("0d4", (0, 4)),
+        ];
+
+        test_parser(parse_pool, cases);
+    }
 }
@@ -0,0 +1,518 @@
+//! Parse errors, with where they happened and what was expected there
+
+use nom::types::CompleteStr;
+use nom::{Context, Err, ErrorKind};
+use std::fmt;
+
+use super::base_terms::is_identifier_char;
+use crate::expression::Expression;
+
+/// what can start a term of an expression
+const TERM: &[&str] = &["a number", "a die", "a variable", "`(`"];
+
+/// what can follow a complete expression
+const AFTER_EXPRESSION: &[&str] = &["an operator", "the end of the expression"];
+
+/// operators that need an expression on their right
+const OPERATORS: &[&str] = &["+", "-", "*", "/", "|", ">=", ">", "<=", "<", "="];
+
+/// characters that can appear in an expression, besides letters, digits and
+/// whitespace
+const SYMBOLS: &str = "+-*/()[]<>=|:,%_";
+
+/// An error in the input of `parse`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParseError {
+    /// position of the error in the input, in bytes
+    pub offset: usize,
+    /// line of the error, starting at 1
+    pub line: usize,
+    /// column of the error in characters, starting at 1
+    pub column: usize,
+    /// what could have come next instead, if anything
+    pub expected: Vec<String>,
+    /// a description of the error for people
+    pub message: String,
+}
+
+impl ParseError {
+    /// Create an error at a position in the input
+    pub fn new(input: &str, offset: usize, message: &str, expected: &[&str]) -> ParseError {
+        let before = &input[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+        ParseError {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            expected: expected
+                .iter()
+                .map(|expected| String::from(*expected))
+                .collect(),
+            message: String::from(message),
+        }
+    }
+
+    /// Describe the failure of the parser on an input
+    pub(crate) fn from_nom(input: &str, err: Err<CompleteStr>) -> ParseError {
+        if let Some(error) = check_delimiters(input) {
+            return error;
+        }
+
+        let (rest, kind) = match err {
+            Err::Error(Context::Code(rest, kind)) | Err::Failure(Context::Code(rest, kind)) => {
+                (rest.0, kind)
+            }
+            Err::Incomplete(_) => ("", ErrorKind::Complete),
+        };
+        let offset = skip_whitespace(input, input.len() - rest.len());
+        let token = next_token(&input[offset..]);
+
+        if token.is_empty() {
+            return ParseError::new(input, offset, "unexpected end of input", TERM);
+        }
+        if kind != ErrorKind::Eof {
+            let message = format!("could not parse the expression starting at `{}`", token);
+            return ParseError::new(input, offset, &message, TERM);
+        }
+
+        // the expression ended early, so find out why it couldn't go on
+        if OPERATORS.contains(&token) {
+            let operand = skip_whitespace(input, offset + token.len());
+            match next_token(&input[operand..]) {
+                "" => {
+                    let message = format!("expected an expression after `{}`", token);
+                    return ParseError::new(input, operand, &message, TERM);
+                }
+                // there can only be one comparison, which is reported below
+                _ if is_comparison(token) => {}
+                next => {
+                    let message = format!("unexpected `{}` after `{}`", next, token);
+                    return ParseError::new(input, operand, &message, TERM);
+                }
+            }
+        }
+        if token == "[" {
+            let name = skip_whitespace(input, offset + 1);
+            return ParseError::new(input, name, "labels must be names", &["a label name"]);
+        }
+
+        let message = format!("unexpected `{}`", token);
+        ParseError::new(input, offset, &message, AFTER_EXPRESSION)
+    }
+
+    /// Check an expression for mistakes that the grammar allows
+    pub(crate) fn check(input: &str, expression: &Expression) -> Result<(), ParseError> {
+        if has_empty_die(expression) {
+            let offset = find_empty_die(input).unwrap_or(0);
+            return Err(ParseError::new(
+                input,
+                offset,
+                "dice must have at least one side",
+                &["a number of sides above 0"],
+            ));
+        }
+
+        if let Some((name, occurrence)) = first_unbound(expression, &mut vec![], &mut vec![]) {
+            let message = format!(
+                "variable `{}` must be bound with `let` before it is used",
+                name
+            );
+            let offset = find_word(input, name, occurrence).unwrap_or(0);
+            return Err(ParseError::new(input, offset, &message, &[]));
+        }
+
+        let mut conditions = 0;
+        if let Some(condition) = first_constant_condition(expression, &mut conditions) {
+            let offset = input
+                .match_indices('|')
+                .nth(condition)
+                .map_or(0, |(offset, _)| skip_whitespace(input, offset + 1));
+            return Err(ParseError::new(
+                input,
+                offset,
+                "condition must use a variable bound with `let`, or it is met the same way by every roll",
+                &["a condition on a variable bound with `let`"],
+            ));
+        }
+
+        let mut choices = 0;
+        if let Some(choice) = first_choice(expression, &mut choices, &|options| {
+            options.iter().all(|(weight, _)| *weight == 0)
+        }) {
+            let offset = find_word(input, "choose", choice).unwrap_or(0);
+            return Err(ParseError::new(
+                input,
+                offset,
+                "choice must have a positive total weight",
+                &["an option with a weight above 0"],
+            ));
+        }
+
+        let mut choices = 0;
+        if let Some(choice) = first_choice(expression, &mut choices, &|options| {
+            let total: u64 = options.iter().map(|(weight, _)| u64::from(*weight)).sum();
+            total > u64::from(u32::MAX)
+        }) {
+            let offset = find_word(input, "choose", choice).unwrap_or(0);
+            let message = format!("choice must have a total weight of at most {}", u32::MAX);
+            return Err(ParseError::new(
+                input,
+                offset,
+                &message,
+                &["options with smaller weights"],
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.line, self.column
+        )?;
+
+        match self.expected.split_last() {
+            None => Ok(()),
+            Some((last, [])) => write!(f, ", expected {}", last),
+            Some((last, rest)) => write!(f, ", expected {} or {}", rest.join(", "), last),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn is_comparison(token: &str) -> bool {
+    [">=", ">", "<=", "<", "="].contains(&token)
+}
+
+/// the offset of the first character at or after `offset` that isn't
+/// whitespace
+fn skip_whitespace(input: &str, offset: usize) -> usize {
+    input[offset..]
+        .find(|c: char| !c.is_whitespace())
+        .map_or(input.len(), |start| offset + start)
+}
+
+/// the word, operator or character at the start of the input
+fn next_token(input: &str) -> &str {
+    let word = input
+        .find(|c: char| !is_identifier_char(c))
+        .unwrap_or(input.len());
+    if word > 0 {
+        return &input[..word];
+    }
+    if input.starts_with(">=") || input.starts_with("<=") {
+        return &input[..2];
+    }
+
+    input.chars().next().map_or("", |c| &input[..c.len_utf8()])
+}
+
+/// Find unknown characters, and brackets that aren't closed or don't match
+fn check_delimiters(input: &str) -> Option<ParseError> {
+    let mut open: Vec<(usize, char)> = vec![];
+
+    for (offset, c) in input.char_indices() {
+        let opener = match c {
+            '(' | '[' => {
+                open.push((offset, c));
+                continue;
+            }
+            ')' => '(',
+            ']' => '[',
+            c if c.is_ascii_alphanumeric() || c.is_whitespace() || SYMBOLS.contains(c) => continue,
+            c => {
+                let message = format!("unexpected character `{}`", c);
+                return Some(ParseError::new(input, offset, &message, &[]));
+            }
+        };
+
+        match open.pop() {
+            Some((_, opening)) if opening == opener => {}
+            Some((_, opening)) => {
+                let message = format!("`{}` doesn't match `{}`", c, opening);
+                let expected = format!("`{}`", matching(opening));
+                return Some(ParseError::new(input, offset, &message, &[&expected]));
+            }
+            None => {
+                let message = format!("`{}` was never opened", c);
+                return Some(ParseError::new(input, offset, &message, AFTER_EXPRESSION));
+            }
+        }
+    }
+
+    open.pop().map(|(offset, opening)| {
+        let message = format!("`{}` is never closed", opening);
+        let expected = format!("`{}`", matching(opening));
+        ParseError::new(input, offset, &message, &[&expected])
+    })
+}
+
+fn matching(opening: char) -> char {
+    match opening {
+        '(' => ')',
+        _ => ']',
+    }
+}
+
+/// the offset of the `occurrence`th use of a name as a whole word, counting
+/// from 0
+fn find_word(input: &str, word: &str, occurrence: usize) -> Option<usize> {
+    input
+        .match_indices(word)
+        .filter(|(offset, _)| {
+            let before = input[..*offset].chars().next_back();
+            let after = input[offset + word.len()..].chars().next();
+            !before.is_some_and(is_identifier_char) && !after.is_some_and(is_identifier_char)
+        })
+        .map(|(offset, _)| offset)
+        .nth(occurrence)
+}
+
+/// whether any die in the expression has no sides
+fn has_empty_die(expression: &Expression) -> bool {
+    match expression {
+        Expression::Die(0) | Expression::Dice { sides: 0, .. } | Expression::Count(_, _, _, 0) => {
+            true
+        }
+        _ => expression.children().into_iter().any(has_empty_die),
+    }
+}
+
+/// the offset of the `d` of the first die without sides, such as `d0` or
+/// `3d00`
+fn find_empty_die(input: &str) -> Option<usize> {
+    input
+        .match_indices('d')
+        .map(|(offset, _)| offset)
+        .find(|offset| {
+            let before = input[..*offset].chars().next_back();
+            let sides = &input[offset + 1..];
+            let digits = sides
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(sides.len());
+            let after = sides[digits..].chars().next();
+
+            !before.is_some_and(|c| is_identifier_char(c) && !c.is_ascii_digit())
+                && digits > 0
+                && sides[..digits].bytes().all(|digit| digit == b'0')
+                && !after.is_some_and(is_identifier_char)
+        })
+}
+
+/// the first variable that is used without being bound, with the number of
+/// times its name appeared before, in the order of the input
+fn first_unbound<'a>(
+    expression: &'a Expression,
+    scope: &mut Vec<&'a str>,
+    seen: &mut Vec<&'a str>,
+) -> Option<(&'a str, usize)> {
+    let occurrence = |seen: &Vec<&str>, name: &str| seen.iter().filter(|s| **s == name).count();
+
+    match expression {
+        Expression::Variable(name) => {
+            if !scope.contains(&name.as_str()) {
+                return Some((name, occurrence(seen, name)));
+            }
+            seen.push(name);
+            None
+        }
+        Expression::Let(name, value, body) => {
+            seen.push(name);
+            let unbound = first_unbound(value, scope, seen);
+            if unbound.is_some() {
+                return unbound;
+            }
+            scope.push(name);
+            let unbound = first_unbound(body, scope, seen);
+            scope.pop();
+            unbound
+        }
+        Expression::Label(label, expr) => {
+            let unbound = first_unbound(expr, scope, seen);
+            seen.push(label);
+            unbound
+        }
+        _ => expression
+            .children()
+            .into_iter()
+            .find_map(|child| first_unbound(child, scope, seen)),
+    }
+}
+
+/// whether a variable appears anywhere in the expression
+fn uses_variable(expression: &Expression) -> bool {
+    match expression {
+        Expression::Variable(_) => true,
+        _ => expression.children().into_iter().any(uses_variable),
+    }
+}
+
+/// the number of conditions before the first condition that uses no
+/// variable, in the order of the input
+fn first_constant_condition(expression: &Expression, conditions: &mut usize) -> Option<usize> {
+    if let Expression::Given(expr, condition) = expression {
+        let constant = first_constant_condition(expr, conditions);
+        if constant.is_some() {
+            return constant;
+        }
+        let index = *conditions;
+        *conditions += 1;
+
+        if !uses_variable(condition) {
+            return Some(index);
+        }
+        return first_constant_condition(condition, conditions);
+    }
+
+    expression
+        .children()
+        .into_iter()
+        .find_map(|child| first_constant_condition(child, conditions))
+}
+
+/// the number of choices before the first choice whose options are `bad`,
+/// in the order of the input
+fn first_choice<F>(expression: &Expression, choices: &mut usize, bad: &F) -> Option<usize>
+where
+    F: Fn(&[(u32, Expression)]) -> bool,
+{
+    if let Expression::Choice(options) = expression {
+        if bad(options) {
+            return Some(*choices);
+        }
+        *choices += 1;
+    }
+
+    expression
+        .children()
+        .into_iter()
+        .find_map(|child| first_choice(child, choices, bad))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse;
+
+    /// the offset, message and expected tokens of the error for an input
+    fn error(input: &str) -> (usize, String, Vec<String>) {
+        let error = parse(input).unwrap_err();
+        (error.offset, error.message, error.expected)
+    }
+
+    #[test]
+    fn position_counts_lines_and_characters() {
+        let actual = parse("let a = d4 in\n  a × 2").unwrap_err();
+
+        assert_eq!(actual.offset, 18);
+        assert_eq!((actual.line, actual.column), (2, 5));
+        assert_eq!(actual.message, "unexpected character `×`");
+        assert_eq!(
+            actual.to_string(),
+            "unexpected character `×` at line 2, column 5"
+        );
+    }
+
+    #[test]
+    fn brackets_must_match() {
+        assert_eq!(error("2d6 + (d4").0, 6);
+        assert_eq!(error("2d6 + (d4").1, "`(` is never closed");
+        assert_eq!(error("2d6 + (d4").2, vec!["`)`"]);
+        assert_eq!(error("d4 )").1, "`)` was never opened");
+        assert_eq!(error("(2d6[fire)]").0, 9);
+        assert_eq!(error("(2d6[fire)]").1, "`)` doesn't match `[`");
+    }
+
+    #[test]
+    fn operators_need_operands() {
+        let (offset, message, expected) = error("2d6 +");
+        assert_eq!(
+            (offset, message.as_str()),
+            (5, "expected an expression after `+`")
+        );
+        assert_eq!(expected, vec!["a number", "a die", "a variable", "`(`"]);
+
+        assert_eq!(error("d4 + E[d6]").0, 5);
+        assert_eq!(error("d4 + E[d6]").1, "unexpected `E` after `+`");
+        assert_eq!(error("d20 >=").1, "expected an expression after `>=`");
+    }
+
+    #[test]
+    fn leftover_input_is_unexpected() {
+        let (offset, message, expected) = error("2d4 extra");
+        assert_eq!((offset, message.as_str()), (4, "unexpected `extra`"));
+        assert_eq!(expected, vec!["an operator", "the end of the expression"]);
+
+        assert_eq!(error("d4 > d6 < d10").0, 8);
+        assert_eq!(error("d4 > d6 < d10").1, "unexpected `<`");
+        assert_eq!(error("2d6[]").1, "labels must be names");
+        assert_eq!(error("2d6[]").0, 4);
+        assert_eq!(
+            parse("2d4 extra").unwrap_err().to_string(),
+            "unexpected `extra` at line 1, column 5, expected an operator or the end of the expression"
+        );
+    }
+
+    #[test]
+    fn empty_input_is_unexpected() {
+        assert_eq!(error("  ").0, 2);
+        assert_eq!(error("  ").1, "unexpected end of input");
+    }
+
+    #[test]
+    fn unbound_variables_are_found() {
+        assert_eq!(
+            error("a + 1").1,
+            "variable `a` must be bound with `let` before it is used"
+        );
+        assert_eq!(error("a + 1").0, 0);
+        // the first two `a` are bound
+        assert_eq!(error("(let a = d4 in a) + a").0, 20);
+        assert_eq!(error("let a = a in a").0, 8);
+        assert_eq!(error("let b = d4 in b + c").0, 18);
+    }
+
+    #[test]
+    fn dice_need_sides() {
+        let (offset, message, _) = error("d6 + 2d0");
+
+        assert_eq!(offset, 6);
+        assert_eq!(message, "dice must have at least one side");
+        assert_eq!(error("count(>=1, 3d00)").0, 12);
+        assert_eq!(error("d10 - d0").0, 6);
+    }
+
+    #[test]
+    fn conditions_use_variables() {
+        let (offset, message, _) = error("d20 | d20 > 1");
+
+        assert_eq!(offset, 6);
+        assert_eq!(
+            message,
+            "condition must use a variable bound with `let`, or it is met the same way by every roll"
+        );
+        // damage given that the attack hit
+        assert!(parse("let a = d20 in 2d6 | a >= 15").is_ok());
+        assert_eq!(error("let a = d4 in (a | a > 1) + (d6 | 1)").0, 34);
+    }
+
+    #[test]
+    fn choices_need_weight() {
+        let (offset, message, _) = error("choose(1: d4) + choose(0: d4, 0: d6)");
+
+        assert_eq!(offset, 16);
+        assert_eq!(message, "choice must have a positive total weight");
+        let (offset, message, _) = error("d4 + choose(4000000000: 1, 1000000000: 2)");
+        assert_eq!(offset, 5);
+        assert_eq!(
+            message,
+            "choice must have a total weight of at most 4294967295"
+        );
+        assert!(parse("choose(4294967294: 1, 1: 2)").is_ok());
+    }
+}
@@ -1,6 +1,9 @@
 mod test_helpers;
 
 mod base_terms;
+mod error;
+#[allow(clippy::module_inception)]
 mod parser;
 
+pub use error::ParseError;
 pub use parser::parse;
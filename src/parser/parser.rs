@@ -1,24 +1,46 @@
 //! Parser Module
 
-use nom;
+use nom::multispace;
 use nom::types::CompleteStr;
 
 use crate::expression::Comparison;
 use crate::expression::Expression;
+use crate::expression::Query;
 use Comparison::*;
 use Expression::*;
 
-use super::base_terms::{parse_constant, parse_die};
+use super::base_terms::{
+    is_identifier_char, parse_constant, parse_die, parse_identifier, parse_pool,
+    parse_signed_number, parse_unsigned_number, parse_variable,
+};
+use super::error::ParseError;
 
 named!(
     parse_base_term<CompleteStr, Expression>,
-    ws!(
-        alt_complete!(
-            parse_functions |
-            parse_parens |
-            parse_die |
-            parse_constant
-        )
+    do_parse!(
+        term:  ws!(
+                   alt_complete!(
+                       parse_functions |
+                       parse_parens |
+                       parse_die |
+                       parse_variable |
+                       parse_constant
+                   )
+               )                           >>
+        label: opt!(complete!(parse_label)) >>
+        (match label {
+            Some(label) => Label(label, Box::new(term)),
+            None => term,
+        })
+    )
+);
+
+named!(
+    parse_label<CompleteStr, String>,
+    delimited!(
+        ws!(tag!("[")),
+        ws!(parse_identifier),
+        ws!(tag!("]"))
     )
 );
 
@@ -26,7 +48,7 @@ named!(
     parse_parens<CompleteStr, Expression>,
     delimited!(
         ws!(tag!("(")),
-        parse_expression,
+        parse_conditional,
         ws!(tag!(")"))
     )
 );
@@ -53,10 +75,51 @@ named!(
     )
 );
 
+named!(
+    parse_count<CompleteStr, Expression>,
+    do_parse!(
+                    ws!(tag!("count"))                  >>
+                    ws!(tag!("("))                      >>
+        comparison: opt!(parse_comparison_operator)     >>
+        face:       ws!(parse_signed_number)            >>
+                    ws!(tag!(","))                      >>
+        pool:       parse_pool                          >>
+                    ws!(tag!(")"))                      >>
+        (Count(comparison.unwrap_or(EqualTo), face, pool.0, pool.1))
+    )
+);
+
+named!(
+    parse_choice_option<CompleteStr, (u32, Expression)>,
+    do_parse!(
+        weight: ws!(parse_unsigned_number) >>
+                opt!(ws!(tag!("%")))       >>
+                ws!(tag!(":"))             >>
+        expr:   parse_expression           >>
+        (weight, expr)
+    )
+);
+
+named!(
+    parse_choice<CompleteStr, Expression>,
+    do_parse!(
+                 ws!(tag!("choose")) >>
+                 ws!(tag!("("))      >>
+        options: separated_nonempty_list_complete!(
+                     ws!(tag!(",")),
+                     parse_choice_option
+                 )                   >>
+                 ws!(tag!(")"))      >>
+        (Choice(options))
+    )
+);
+
 named!(
     parse_functions<CompleteStr, Expression>,
     alt_complete!(
-        parse_unary_function
+        parse_unary_function |
+        parse_count |
+        parse_choice
     )
 );
 
@@ -107,37 +170,40 @@ named!(
 );
 
 named!(
-    parse_comparison<CompleteStr, Expression>,
+    parse_comparison_operator<CompleteStr, Comparison>,
     map_res!(
-        do_parse!(
-            left: ws!(parse_sum) >>
-            operator: ws!(
-                alt_complete!(
-                    tag!(">=") |
-                    tag!(">") |
-                    tag!("<=") |
-                    tag!("<") |
-                    tag!("=")
-                )
-            ) >>
-            right: ws!(parse_sum) >>
-            (left, right, operator)
+        ws!(
+            alt_complete!(
+                tag!(">=") |
+                tag!(">") |
+                tag!("<=") |
+                tag!("<") |
+                tag!("=")
+            )
         ),
-        |(left, right, CompleteStr(operator)): (Expression, Expression, CompleteStr)| -> Result<Expression, &str> {
-            let comparison = match operator {
+        |CompleteStr(operator): CompleteStr| -> Result<Comparison, &str> {
+            match operator {
                 ">=" => Ok(GreaterThanOrEqualTo),
                 ">" => Ok(GreaterThan),
                 "<=" => Ok(LessThanOrEqualTo),
                 "<" => Ok(LessThan),
                 "=" => Ok(EqualTo),
                 _ => Err("unknown comparison operator"),
-            }?;
-
-            Ok(Compare(Box::new(left), Box::new(right), comparison))
+            }
         }
     )
 );
 
+named!(
+    parse_comparison<CompleteStr, Expression>,
+    do_parse!(
+        left:       ws!(parse_sum)            >>
+        comparison: parse_comparison_operator >>
+        right:      ws!(parse_sum)            >>
+        (Compare(Box::new(left), Box::new(right), comparison))
+    )
+);
+
 named!(
     parse_expression<CompleteStr, Expression>,
     alt_complete!(
@@ -146,22 +212,104 @@ named!(
     )
 );
 
+named!(
+    parse_brackets<CompleteStr, Expression>,
+    delimited!(
+        ws!(tag!("[")),
+        parse_conditional,
+        ws!(tag!("]"))
+    )
+);
+
+named!(
+    parse_query<CompleteStr, Expression>,
+    alt_complete!(
+        map!(
+            preceded!(ws!(tag!("P")), parse_parens),
+            |expr| Expression::Query(Query::Probability, Box::new(expr))
+        ) |
+        map!(
+            preceded!(ws!(tag!("E")), parse_brackets),
+            |expr| Expression::Query(Query::Expectation, Box::new(expr))
+        ) |
+        map!(
+            preceded!(ws!(tag!("Var")), parse_brackets),
+            |expr| Expression::Query(Query::Variance, Box::new(expr))
+        )
+    )
+);
+
+named!(
+    parse_given<CompleteStr, Expression>,
+    do_parse!(
+        expr:      parse_expression >>
+        condition: opt!(
+                       complete!(
+                           preceded!(ws!(tag!("|")), parse_expression)
+                       )
+                   )                >>
+        (match condition {
+            Some(condition) => Given(Box::new(expr), Box::new(condition)),
+            None => expr,
+        })
+    )
+);
+
+named_args!(
+    parse_keyword<'a>(keyword: &'a str)<CompleteStr<'a>, CompleteStr<'a>>,
+    delimited!(
+        opt!(multispace),
+        terminated!(
+            tag!(keyword),
+            not!(take_while1!(is_identifier_char))
+        ),
+        opt!(multispace)
+    )
+);
+
+named!(
+    parse_let<CompleteStr, Expression>,
+    do_parse!(
+               call!(parse_keyword, "let") >>
+        name:  ws!(parse_identifier)       >>
+               ws!(tag!("="))              >>
+        value: parse_expression            >>
+               call!(parse_keyword, "in")  >>
+        body:  parse_conditional           >>
+        (Let(name, Box::new(value), Box::new(body)))
+    )
+);
+
+named!(
+    parse_conditional<CompleteStr, Expression>,
+    alt_complete!(
+        parse_let |
+        parse_given
+    )
+);
+
 named!(
     parse_full_expression<CompleteStr, Expression>,
     do_parse!(
-        expr:   parse_expression >>
-                ws!(eof!())      >>
+        expr:   alt_complete!(parse_query | parse_conditional) >>
+                ws!(eof!())                                    >>
         (expr)
     )
 );
 
 /// parse an input string into an `Expression` and report errors
-pub fn parse(input: &str) -> Result<Expression, nom::Err<CompleteStr>> {
-    let result = parse_full_expression(input.into());
-    match result {
-        Ok((_, expr)) => Ok(expr),
-        Err(err) => Err(err),
-    }
+///
+/// the grammar allows a few mistakes, such as unbound variables, choices
+/// without any weight and conditions that use no variable, which are checked
+/// once the whole input is parsed
+pub fn parse(input: &str) -> Result<Expression, ParseError> {
+    let expr = match parse_full_expression(input.into()) {
+        Ok((_, expr)) => expr,
+        Err(err) => return Err(ParseError::from_nom(input, err)),
+    };
+
+    ParseError::check(input, &expr)?;
+    Ok(expr)
 }
 
 #[cfg(test)]
@@ -188,7 +336,7 @@ mod tests {
         let cases = vec![
             (
                 "dis(2d4)",
-                Disadvantage(Box::new(Sum(Box::new(Die(4)), Box::new(Die(4))))),
+                Disadvantage(Box::new(Dice { count: 2, sides: 4 })),
             ),
             (" dis ( d4 ) ", Disadvantage(Box::new(Die(4)))),
             ("adv(d4)", Advantage(Box::new(Die(4)))),
@@ -209,6 +357,40 @@ mod tests {
         test_parser(parse_unary_function, cases);
     }
 
+    #[test]
+    fn test_parse_count() {
+        let cases = vec![
+            ("count(6, 5d6)", Count(EqualTo, 6, 5, 6)),
+            (" count ( = 1 , 3d6 ) ", Count(EqualTo, 1, 3, 6)),
+            ("count(>=5, 10d6)", Count(GreaterThanOrEqualTo, 5, 10, 6)),
+            ("count(> 4, d8)", Count(GreaterThan, 4, 1, 8)),
+            ("count(<2, 4d4)", Count(LessThan, 2, 4, 4)),
+            ("count(<=-1, 4d4)", Count(LessThanOrEqualTo, -1, 4, 4)),
+        ];
+
+        test_parser(parse_count, cases);
+    }
+
+    #[test]
+    fn test_parse_choice() {
+        let cases = vec![
+            (
+                "choose(50%: 2d6, 50%: 1d6)",
+                Choice(vec![(50, Dice { count: 2, sides: 6 }), (50, Die(6))]),
+            ),
+            (
+                " choose ( 3 : d4 + 1 , 1 : 0 ) ",
+                Choice(vec![
+                    (3, Sum(Box::new(Die(4)), Box::new(Constant(1)))),
+                    (1, Constant(0)),
+                ]),
+            ),
+            ("choose(1: d20)", Choice(vec![(1, Die(20))])),
+        ];
+
+        test_parser(parse_choice, cases);
+    }
+
     #[test]
     fn test_parse_sum() {
         let cases = vec![
@@ -388,15 +570,166 @@ mod tests {
                 "d4 = d4",
                 Compare(Box::new(Die(4)), Box::new(Die(4)), EqualTo),
             ),
+            (
+                "count(1, 5d6) * 2 > 5",
+                Compare(
+                    Box::new(Multiply(
+                        Box::new(Count(EqualTo, 1, 5, 6)),
+                        Box::new(Constant(2)),
+                    )),
+                    Box::new(Constant(5)),
+                    GreaterThan,
+                ),
+            ),
         ];
 
         test_parser(parse_expression, cases);
     }
 
+    #[test]
+    fn test_parse_query() {
+        let cases = vec![
+            (
+                "P(2d6 >= 8)",
+                Expression::Query(
+                    Query::Probability,
+                    Box::new(Compare(
+                        Box::new(Dice { count: 2, sides: 6 }),
+                        Box::new(Constant(8)),
+                        GreaterThanOrEqualTo,
+                    )),
+                ),
+            ),
+            (
+                " E [ 2d6 + 3 ] ",
+                Expression::Query(
+                    Query::Expectation,
+                    Box::new(Sum(
+                        Box::new(Dice { count: 2, sides: 6 }),
+                        Box::new(Constant(3)),
+                    )),
+                ),
+            ),
+            (
+                "Var[adv(d20)]",
+                Expression::Query(Query::Variance, Box::new(Advantage(Box::new(Die(20))))),
+            ),
+        ];
+
+        test_parser(parse_query, cases);
+    }
+
+    #[test]
+    fn test_parse_conditional() {
+        let a = || Box::new(Variable(String::from("a")));
+        let cases = vec![
+            (
+                "let a = d20 in a | a > 1",
+                Let(
+                    String::from("a"),
+                    Box::new(Die(20)),
+                    Box::new(Given(
+                        a(),
+                        Box::new(Compare(a(), Box::new(Constant(1)), GreaterThan)),
+                    )),
+                ),
+            ),
+            (
+                " let a = d20 in let b = d4 in a + b ",
+                Let(
+                    String::from("a"),
+                    Box::new(Die(20)),
+                    Box::new(Let(
+                        String::from("b"),
+                        Box::new(Die(4)),
+                        Box::new(Sum(a(), Box::new(Variable(String::from("b"))))),
+                    )),
+                ),
+            ),
+            (
+                "let a = 2d6 = 7 in a",
+                Let(
+                    String::from("a"),
+                    Box::new(Compare(
+                        Box::new(Dice { count: 2, sides: 6 }),
+                        Box::new(Constant(7)),
+                        EqualTo,
+                    )),
+                    a(),
+                ),
+            ),
+            (
+                "(let a = d6 in a * a) + 1",
+                Sum(
+                    Box::new(Let(
+                        String::from("a"),
+                        Box::new(Die(6)),
+                        Box::new(Multiply(a(), a())),
+                    )),
+                    Box::new(Constant(1)),
+                ),
+            ),
+        ];
+
+        test_parser(parse_conditional, cases);
+    }
+
+    #[test]
+    fn test_parse_label() {
+        let label = |name: &str, expr: Expression| Label(String::from(name), Box::new(expr));
+        let cases = vec![
+            (
+                "2d6[fire] + 1d8[slashing]",
+                Sum(
+                    Box::new(label("fire", Dice { count: 2, sides: 6 })),
+                    Box::new(label("slashing", Die(8))),
+                ),
+            ),
+            (
+                " (d6 + 2) [ fire ] * 2 ",
+                Multiply(
+                    Box::new(label("fire", Sum(Box::new(Die(6)), Box::new(Constant(2))))),
+                    Box::new(Constant(2)),
+                ),
+            ),
+            (
+                "adv(d20)[luck]",
+                label("luck", Advantage(Box::new(Die(20)))),
+            ),
+        ];
+
+        test_parser(parse_expression, cases);
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let cases = vec![
+            "2d6 + 3",
+            "d20 - (d4 - 1)",
+            "(d6 + 1) * -2",
+            "d4 * d4 / (d2 * 2)",
+            "adv(d20 + 5) >= 15",
+            "count(>=5, 6d6) = 2",
+            "choose(50: d4, 50: d6 * 2)",
+            "P(2d6 >= 8)",
+            "E[(d6 + 2)[fire] + d8[cold]]",
+            "Var[dis(d20)]",
+            "let a = d20 in (a + 5 >= 15) * 2d6 | a > 1",
+            "let a = (let b = d4 in b * b) in a + a",
+            "(let a = d20 in a | a > 1) + 1",
+        ];
+
+        for input in cases {
+            let expression = parse(input).unwrap();
+            assert_eq!(expression.to_string(), input);
+            assert_eq!(parse(&expression.to_string()), Ok(expression));
+        }
+    }
+
     #[test]
     fn test_parse() {
         let cases: Vec<(&str, Result<Expression, &str>)> = vec![
-            ("2d4", Ok(Sum(Box::new(Die(4)), Box::new(Die(4))))),
+            ("2d4", Ok(Dice { count: 2, sides: 4 })),
             // trailing whitespace is ok
             (" \t\nd10 \t\n", Ok(Die(10))),
             // entire unknown keyword
@@ -405,6 +738,55 @@ mod tests {
             ("d4 d4", Err("")),
             // only one comparison operator allowed
             ("d4 > d6 < d10", Err("")),
+            // choices need at least one option with weight
+            ("choose()", Err("")),
+            ("choose(0: d4, 0: d6)", Err("")),
+            // and a total weight that fits in a `u32`
+            ("choose(4000000000: 1, 1000000000: 2)", Err("")),
+            // queries are only allowed around the entire expression
+            ("P(d20 > 10) + 1", Err("")),
+            ("d4 + E[d6]", Err("")),
+            // variables must be bound before they are used
+            ("a + 1", Err("")),
+            ("(let a = d4 in a) + a", Err("")),
+            ("let a = a in a", Err("")),
+            // conditions must use a variable
+            ("d20 | d20 > 1", Err("")),
+            // labels must be names
+            ("2d6[]", Err("")),
+            ("2d6[2]", Err("")),
+            ("leta = d4 in a", Err("")),
+            (
+                "let a = d4 in a | a > 2",
+                Ok(Let(
+                    String::from("a"),
+                    Box::new(Die(4)),
+                    Box::new(Given(
+                        Box::new(Variable(String::from("a"))),
+                        Box::new(Compare(
+                            Box::new(Variable(String::from("a"))),
+                            Box::new(Constant(2)),
+                            GreaterThan,
+                        )),
+                    )),
+                )),
+            ),
+            // conditions on a variable weight the expression by their chance
+            (
+                "let a = d20 in 2d6 | a >= 15",
+                Ok(Let(
+                    String::from("a"),
+                    Box::new(Die(20)),
+                    Box::new(Given(
+                        Box::new(Dice { count: 2, sides: 6 }),
+                        Box::new(Compare(
+                            Box::new(Variable(String::from("a"))),
+                            Box::new(Constant(15)),
+                            GreaterThanOrEqualTo,
+                        )),
+                    )),
+                )),
+            ),
         ];
 
         for (input, expected) in cases {
@@ -11,11 +11,9 @@ where
     O: std::cmp::PartialEq,
 {
     for (index, (input, expected)) in cases.iter().enumerate() {
-        let actual = (test)(input.clone().into());
-        let (rest, actual) = actual.expect(&format!(
-            "[{}]: failed to parse expression \"{}\"",
-            index, input
-        ));
+        let actual = (test)((*input).into());
+        let (rest, actual) = actual
+            .unwrap_or_else(|_| panic!("[{}]: failed to parse expression \"{}\"", index, input));
         assert_eq!(
             actual,
             *expected,
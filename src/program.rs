@@ -0,0 +1,445 @@
+//! Program Module
+//!
+//! Expressions compiled into a flat list of instructions for a stack
+//! machine, so that rolling many times doesn't walk the tree of the
+//! expression. A program rolls exactly like the expression it was compiled
+//! from, drawing the same random numbers in the same order, so the same seed
+//! gives the same rolls.
+
+use crate::expression::{roll_die, Comparison, Expression, UnmetCondition, MAX_ATTEMPTS};
+use crate::operators;
+use crate::random::{below, seeded_rng};
+use crate::simulate::Histogram;
+use crate::traits::{PlotResult, RollResult};
+use rand::Rng;
+
+/// An operation on the top two values of the stack
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Sum,
+    Difference,
+    Multiply,
+    Divide,
+    Advantage,
+    Disadvantage,
+    GreaterThan,
+    GreaterThanOrEqualTo,
+    LessThan,
+    LessThanOrEqualTo,
+    EqualTo,
+}
+
+impl Operator {
+    fn comparison(comparison: &Comparison) -> Operator {
+        match comparison {
+            Comparison::GreaterThan => Operator::GreaterThan,
+            Comparison::GreaterThanOrEqualTo => Operator::GreaterThanOrEqualTo,
+            Comparison::LessThan => Operator::LessThan,
+            Comparison::LessThanOrEqualTo => Operator::LessThanOrEqualTo,
+            Comparison::EqualTo => Operator::EqualTo,
+        }
+    }
+
+    fn apply(self, left: RollResult, right: RollResult) -> RollResult {
+        let (left, right) = (&left, &right);
+        match self {
+            Operator::Sum => operators::sum(left, right),
+            Operator::Difference => operators::difference(left, right),
+            Operator::Multiply => operators::multiply(left, right),
+            Operator::Divide => operators::divide(left, right),
+            Operator::Advantage => operators::advantage(left, right),
+            Operator::Disadvantage => operators::disadvantage(left, right),
+            Operator::GreaterThan => operators::greater_than(left, right),
+            Operator::GreaterThanOrEqualTo => operators::greater_than_or_equal_to(left, right),
+            Operator::LessThan => operators::less_than(left, right),
+            Operator::LessThanOrEqualTo => operators::less_than_or_equal_to(left, right),
+            Operator::EqualTo => operators::equal_to(left, right),
+        }
+    }
+}
+
+/// A single step of a program
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Instruction {
+    /// push a constant
+    Push(RollResult),
+    /// roll a pool of `count` dice with `sides` faces, and push the sum
+    Dice {
+        count: u32,
+        sides: u32,
+    },
+    /// roll a pool of `count` dice with `sides` faces, and push the number
+    /// of dice that satisfy a comparison against `face`
+    Count {
+        operator: Operator,
+        face: RollResult,
+        count: u32,
+        sides: u32,
+    },
+    /// pop two values, and push the operator applied to them
+    Apply(Operator),
+    /// push the value of a variable
+    Load(usize),
+    /// pop a value into a variable
+    Store(usize),
+    /// pop a condition, and throw out the roll if it is zero
+    Require,
+    /// pick one of the options of a choice, and jump to it
+    Choose(usize),
+    Jump(usize),
+    /// fail like the tree walker when a variable was never bound
+    Unbound(usize),
+}
+
+/// One of several options of a choice, with its weight and the start of its
+/// instructions
+type Options = Vec<(u32, usize)>;
+
+/// An expression compiled into a flat list of instructions for a stack
+/// machine, which can be shared between threads
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+    /// the options of every choice, with their total weight
+    choices: Vec<(u32, Options)>,
+    /// the names of variables that are never bound
+    unbound: Vec<String>,
+    /// the number of variables
+    slots: usize,
+}
+
+/// Values and variables of a running program, reused between rolls
+#[derive(Default)]
+struct Machine {
+    stack: Vec<RollResult>,
+    slots: Vec<RollResult>,
+}
+
+impl Program {
+    /// Compile an expression into a program
+    pub fn compile(expression: &Expression) -> Program {
+        let mut program = Program {
+            instructions: vec![],
+            choices: vec![],
+            unbound: vec![],
+            slots: 0,
+        };
+        program.emit(expression, &mut vec![]);
+
+        program
+    }
+
+    /// Add the instructions of an expression, with the variables currently
+    /// in scope and their slots
+    fn emit(&mut self, expression: &Expression, scope: &mut Vec<(String, usize)>) {
+        use Expression::*;
+
+        let instruction = match expression {
+            Constant(value) => Instruction::Push(*value),
+            Die(sides) => Instruction::Dice {
+                count: 1,
+                sides: *sides,
+            },
+            Dice { count, sides } => Instruction::Dice {
+                count: *count,
+                sides: *sides,
+            },
+            Count(comparison, face, count, sides) => Instruction::Count {
+                operator: Operator::comparison(comparison),
+                face: *face,
+                count: *count,
+                sides: *sides,
+            },
+            Variable(name) => match scope.iter().rev().find(|(bound, _)| bound == name) {
+                Some((_, slot)) => Instruction::Load(*slot),
+                None => {
+                    self.unbound.push(name.clone());
+                    Instruction::Unbound(self.unbound.len() - 1)
+                }
+            },
+            Query(_, expr) | Label(_, expr) => return self.emit(expr, scope),
+            Let(name, value, body) => {
+                self.emit(value, scope);
+                let slot = self.slots;
+                self.slots += 1;
+                self.instructions.push(Instruction::Store(slot));
+
+                scope.push((name.clone(), slot));
+                self.emit(body, scope);
+                scope.pop();
+                return;
+            }
+            Given(expr, condition) => {
+                self.emit(expr, scope);
+                self.emit(condition, scope);
+                Instruction::Require
+            }
+            Choice(options) => {
+                let choice = self.choices.len();
+                let total = options.iter().map(|(weight, _)| weight).sum();
+                self.choices.push((total, vec![]));
+                self.instructions.push(Instruction::Choose(choice));
+
+                let mut jumps = vec![];
+                for (weight, option) in options {
+                    let start = self.instructions.len();
+                    self.choices[choice].1.push((*weight, start));
+                    self.emit(option, scope);
+                    jumps.push(self.instructions.len());
+                    self.instructions.push(Instruction::Jump(0));
+                }
+
+                // every option continues after the last one
+                let end = self.instructions.len();
+                for jump in jumps {
+                    self.instructions[jump] = Instruction::Jump(end);
+                }
+                return;
+            }
+            Sum(left, right) => self.emit_operation(Operator::Sum, left, right, scope),
+            Diff(left, right) => self.emit_operation(Operator::Difference, left, right, scope),
+            Multiply(left, right) => self.emit_operation(Operator::Multiply, left, right, scope),
+            Divide(left, right) => self.emit_operation(Operator::Divide, left, right, scope),
+            // both rolls are independent
+            Advantage(expr) => self.emit_operation(Operator::Advantage, expr, expr, scope),
+            Disadvantage(expr) => self.emit_operation(Operator::Disadvantage, expr, expr, scope),
+            Compare(left, right, comparison) => {
+                self.emit_operation(Operator::comparison(comparison), left, right, scope)
+            }
+        };
+
+        self.instructions.push(instruction);
+    }
+
+    /// Add the instructions of both operands, and return the operation
+    fn emit_operation(
+        &mut self,
+        operator: Operator,
+        left: &Expression,
+        right: &Expression,
+        scope: &mut Vec<(String, usize)>,
+    ) -> Instruction {
+        self.emit(left, scope);
+        self.emit(right, scope);
+        Instruction::Apply(operator)
+    }
+
+    /// Run the program once, or `None` if a condition was not met
+    fn run<R: Rng + ?Sized>(&self, machine: &mut Machine, rng: &mut R) -> Option<RollResult> {
+        let stack = &mut machine.stack;
+        stack.clear();
+        machine.slots.resize(self.slots, 0);
+
+        let mut counter = 0;
+        while let Some(instruction) = self.instructions.get(counter) {
+            counter += 1;
+            match *instruction {
+                Instruction::Push(value) => stack.push(value),
+                Instruction::Dice { count, sides } => {
+                    stack.push((0..count).map(|_| roll_die(sides, rng)).sum())
+                }
+                Instruction::Count {
+                    operator,
+                    face,
+                    count,
+                    sides,
+                } => stack.push(
+                    (0..count)
+                        .map(|_| roll_die(sides, rng))
+                        .filter(|roll| operator.apply(*roll, face) == 1)
+                        .count() as RollResult,
+                ),
+                Instruction::Apply(operator) => {
+                    let right = stack.pop().expect("program has a right operand");
+                    let left = stack.pop().expect("program has a left operand");
+                    stack.push(operator.apply(left, right));
+                }
+                Instruction::Load(slot) => stack.push(machine.slots[slot]),
+                Instruction::Store(slot) => {
+                    machine.slots[slot] = stack.pop().expect("program has a value to bind")
+                }
+                Instruction::Require => {
+                    if stack.pop().expect("program has a condition") == 0 {
+                        return None;
+                    }
+                }
+                Instruction::Choose(choice) => {
+                    let (total, options) = &self.choices[choice];
+                    let mut pick = below(*total, rng);
+                    let (_, start) = options
+                        .iter()
+                        .find(|(weight, _)| {
+                            if pick < *weight {
+                                return true;
+                            }
+                            pick -= weight;
+                            false
+                        })
+                        .expect("choice must have a positive total weight");
+                    counter = *start;
+                }
+                Instruction::Jump(target) => counter = target,
+                Instruction::Unbound(name) => panic!("unbound variable `{}`", self.unbound[name]),
+            }
+        }
+
+        stack.pop()
+    }
+
+    /// Roll the program, throwing out rolls that do not meet every condition
+    fn roll_in<R: Rng + ?Sized>(
+        &self,
+        machine: &mut Machine,
+        rng: &mut R,
+    ) -> Result<RollResult, UnmetCondition> {
+        (0..MAX_ATTEMPTS)
+            .filter_map(|_| self.run(machine, rng))
+            .next()
+            .ok_or(UnmetCondition)
+    }
+
+    /// Get a single value, exactly as `Expression::try_roll_with` would for
+    /// the expression, using the given random number generator
+    pub fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<RollResult, UnmetCondition> {
+        self.roll_in(&mut Machine::default(), rng)
+    }
+
+    /// Get a single value
+    pub fn roll(&self) -> Result<RollResult, UnmetCondition> {
+        self.roll_with(&mut rand::thread_rng())
+    }
+
+    /// Get a single value that is always the same for the same seed, like
+    /// `Rollable::roll_seeded`
+    pub fn roll_seeded(&self, seed: u64) -> Result<RollResult, UnmetCondition> {
+        self.roll_with(&mut seeded_rng(seed))
+    }
+
+    /// Roll the program many times, counting every outcome, and stopping
+    /// early at the first roll whose conditions could not be met
+    pub(crate) fn count_rolls<R: Rng + ?Sized>(&self, count: u64, rng: &mut R) -> Histogram {
+        let mut machine = Machine::default();
+        let mut histogram = Histogram::default();
+        for _ in 0..count {
+            match self.roll_in(&mut machine, rng) {
+                Ok(roll) => histogram.add(roll),
+                Err(UnmetCondition) => break,
+            }
+        }
+
+        histogram
+    }
+
+    /// Roll the program `count` times using the given random number
+    /// generator, and get the fraction of rolls of every outcome, like
+    /// `Expression::roll_many_with`
+    ///
+    /// if the conditions of a roll can't be met, the rest aren't rolled, and
+    /// the total is the number of rolls that were
+    pub fn roll_many_with<R: Rng + ?Sized>(&self, count: u64, rng: &mut R) -> PlotResult {
+        self.count_rolls(count, rng).to_plot()
+    }
+}
+
+impl Expression {
+    /// Compile the expression into a program, for rolling many times
+    pub fn compile(&self) -> Program {
+        Program::compile(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::Rollable;
+
+    fn assert_same_rolls(input: &str) {
+        let expression = crate::parse(input).unwrap();
+        let program = expression.compile();
+        let (mut expected_rng, mut actual_rng) = (seeded_rng(11), seeded_rng(11));
+
+        for _ in 0..200 {
+            assert_eq!(
+                expression.roll_with(&mut expected_rng),
+                program.roll_with(&mut actual_rng).unwrap(),
+                "{}",
+                input
+            );
+        }
+        // both drew the same random numbers
+        assert_eq!(expected_rng, actual_rng, "{}", input);
+    }
+
+    #[test]
+    fn rolls_match_tree_walker() {
+        let inputs = [
+            "4d6 + d8 - 3",
+            "2d6 * d4 / d3",
+            "adv(d20) + dis(2d4)",
+            "d20 >= 11",
+            "d6 < 3",
+            "count(>=5, 6d6) = count(<2, 3d4)",
+            "choose(1: 4d6, 2: adv(d20), 3: 5)",
+            "choose(0: d4, 2: d6) + choose(1: d8, 1: -2)",
+            "let a = d20 in let b = a + d4 in b - a",
+            "let a = d6 in (let a = d8 in a) + a",
+            "let a = d20 in a | a > 15",
+            "let a = 3d6 in let b = d6 in a + b | b >= 4",
+            "(let a = d8 in a | a > 4) + (let b = d6 in b * 2 | b = 1)",
+            "2d6[fire] + d8[slashing]",
+            "E[2d6 + 3]",
+        ];
+
+        for input in inputs.iter() {
+            assert_same_rolls(input);
+        }
+    }
+
+    #[test]
+    fn choices_jump_past_other_options() {
+        let program = crate::parse("choose(1: 1, 1: 2) * 10").unwrap().compile();
+
+        assert_eq!(
+            program.instructions,
+            vec![
+                Instruction::Choose(0),
+                Instruction::Push(1),
+                Instruction::Jump(5),
+                Instruction::Push(2),
+                Instruction::Jump(5),
+                Instruction::Push(10),
+                Instruction::Apply(Operator::Multiply),
+            ]
+        );
+        assert_eq!(program.choices, vec![(2, vec![(1, 1), (1, 3)])]);
+    }
+
+    #[test]
+    fn seeded_rolls_match() {
+        let expression = crate::parse("let a = 2d6 in a * a | a > 7").unwrap();
+        let program = expression.compile();
+
+        assert_eq!(program.roll_seeded(8), Ok(expression.roll_seeded(8)));
+        assert_eq!(program.roll_many_with(100, &mut seeded_rng(3)).total, 100.0);
+    }
+
+    #[test]
+    fn impossible_conditions_fail_to_roll() {
+        let program = crate::parse("let a = d6 in a | a > 6").unwrap().compile();
+
+        assert_eq!(program.roll_seeded(1), Err(UnmetCondition));
+        assert_eq!(program.roll_many_with(10, &mut seeded_rng(1)).total, 0.0);
+    }
+
+    #[test]
+    fn programs_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Program>();
+    }
+
+    #[test]
+    #[should_panic(expected = "unbound variable `a`")]
+    fn unbound_variables_fail_when_rolled() {
+        let _ = Expression::Variable(String::from("a")).compile().roll();
+    }
+}
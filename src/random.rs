@@ -0,0 +1,202 @@
+//! Random Module
+//!
+//! Random number generators for rolling dice, and the algorithm that turns
+//! random numbers into dice rolls.
+//!
+//! Seeded rolls are meant to be shared, so they can't depend on the
+//! algorithms of the `rand` crate, which may change between versions. Instead,
+//! pips ships its own stream, identified by `STREAM_VERSION`. For the same
+//! expression, seed and stream version, every roll and roll breakdown is
+//! identical on every platform, including wasm32.
+//!
+//! # Stream version 1
+//!
+//! - Random numbers come from PCG32 (XSH RR, 64 bit state, 32 bit output), as
+//!   described at <https://www.pcg-random.org>. `seeded_rng(seed)` starts it
+//!   like the reference `pcg32_srandom_r(seed, SEEDED_STREAM)`.
+//! - A number below `n` is drawn by taking the next 32 bit output `x`,
+//!   rejecting it while `x >= 2^32 - (2^32 mod n)`, and returning `x mod n`.
+//! - A die with `sides` faces rolls a number below `sides`, plus 1.
+//! - A choice draws a number below the total weight, and picks the first
+//!   option whose cumulative weight is greater than it.
+//! - Expressions are rolled depth first, from left to right, and every die in
+//!   a pool is rolled in order. Both operands of `adv` and `dis` are rolled.
+//! - A roll that fails a condition is thrown out, and the whole expression is
+//!   rolled again, continuing from the current state of the stream.
+
+use rand::{Error, RngCore};
+
+/// The version of the stream of seeded rolls. This changes whenever the same
+/// seed would produce different rolls.
+pub const STREAM_VERSION: u32 = 1;
+
+/// The PCG32 stream used by `seeded_rng`
+pub const SEEDED_STREAM: u64 = 54;
+
+const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+/// The PCG32 random number generator, as used for seeded rolls
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiceRng {
+    state: u64,
+    increment: u64,
+}
+
+impl DiceRng {
+    /// Create a generator with an initial state and a stream, like the
+    /// reference `pcg32_srandom_r(state, stream)`
+    pub fn new(state: u64, stream: u64) -> DiceRng {
+        let mut rng = DiceRng {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.step();
+        rng.state = rng.state.wrapping_add(state);
+        rng.step();
+
+        rng
+    }
+
+    fn step(&mut self) {
+        self.state = self
+            .state
+            .wrapping_mul(MULTIPLIER)
+            .wrapping_add(self.increment);
+    }
+}
+
+impl RngCore for DiceRng {
+    fn next_u32(&mut self) -> u32 {
+        let state = self.state;
+        self.step();
+
+        let xorshifted = (((state >> 18) ^ state) >> 27) as u32;
+        let rotation = (state >> 59) as u32;
+        xorshifted.rotate_right(rotation)
+    }
+
+    /// two outputs, with the first as the low bits
+    fn next_u64(&mut self) -> u64 {
+        let low = u64::from(self.next_u32());
+        let high = u64::from(self.next_u32());
+        (high << 32) | low
+    }
+
+    /// one output for every 4 bytes, in little endian order
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Create the random number generator used for seeded rolls
+pub fn seeded_rng(seed: u64) -> DiceRng {
+    DiceRng::new(seed, SEEDED_STREAM)
+}
+
+/// Draw a number below `bound` without bias, using only 32 bit outputs
+///
+/// `bound` must be positive
+pub fn below<R: RngCore + ?Sized>(bound: u32, rng: &mut R) -> u32 {
+    assert!(bound > 0, "can't draw a number below 0");
+    // the highest multiple of `bound` that fits in 32 bits, as a 64 bit
+    // number so that it can be 2^32
+    let zone = (1u64 << 32) - (1u64 << 32) % u64::from(bound);
+
+    loop {
+        let value = rng.next_u32();
+        if u64::from(value) < zone {
+            return value % bound;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::Expression;
+    use crate::traits::Rollable;
+
+    #[test]
+    fn matches_reference_pcg32() {
+        // from the demo of the reference implementation, seeded with 42 on
+        // stream 54
+        let mut rng = DiceRng::new(42, 54);
+        let outputs: Vec<u32> = (0..6).map(|_| rng.next_u32()).collect();
+
+        assert_eq!(
+            outputs,
+            vec![0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e]
+        );
+    }
+
+    #[test]
+    fn below_rejects_biased_outputs() {
+        /// replays fixed outputs
+        struct Fixed(Vec<u32>);
+        impl RngCore for Fixed {
+            fn next_u32(&mut self) -> u32 {
+                self.0.remove(0)
+            }
+            fn next_u64(&mut self) -> u64 {
+                u64::from(self.next_u32())
+            }
+            fn fill_bytes(&mut self, _: &mut [u8]) {}
+            fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        // 2^32 mod 6 is 4, so the 4 highest outputs are rejected
+        let mut rng = Fixed(vec![u32::MAX, u32::MAX - 3, u32::MAX - 4, 13]);
+        assert_eq!(below(6, &mut rng), 5);
+        assert_eq!(below(6, &mut rng), 1);
+        assert_eq!(below(1, &mut Fixed(vec![u32::MAX])), 0);
+    }
+
+    #[test]
+    fn seeded_stream_is_stable() {
+        // these must never change without a new stream version, or shared
+        // seeds would roll differently
+        let mut rng = seeded_rng(42);
+        let rolls: Vec<i32> = (0..10)
+            .map(|_| Expression::Die(20).roll_with(&mut rng))
+            .collect();
+
+        assert_eq!(rolls, vec![4, 18, 5, 16, 16, 7, 6, 6, 15, 5]);
+
+        let expression = crate::parse("choose(1: 4d6, 2: adv(d20)) + count(6, 3d6)").unwrap();
+        assert_eq!(
+            expression
+                .roll_detailed_with(&mut seeded_rng(7))
+                .unwrap()
+                .to_string(),
+            [
+                "choose(1: 4d6, 2: adv(d20)) + count(=6, 3d6) = 14",
+                "  choose(1: 4d6, 2: adv(d20)) = 14",
+                "    adv(d20) = 14",
+                "      d20 → [9] = 9 (dropped)",
+                "      d20 → [14] = 14",
+                "  count(=6, 3d6) → [3, 1, 2] = 0",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn seeded_traces_are_repeatable() {
+        let expression = crate::parse("let a = d20 in 4d6 + a | a > 5").unwrap();
+
+        assert_eq!(
+            expression.roll_detailed_with(&mut seeded_rng(1)),
+            expression.roll_detailed_with(&mut seeded_rng(1))
+        );
+    }
+}
@@ -0,0 +1,660 @@
+//! Record Module
+//!
+//! Rolls with several named outcomes from the same dice, such as the STUN and
+//! BODY of a Hero System damage roll.
+
+use crate::distribution::Distribution;
+use crate::expression::{Expression, Scope, UnmetCondition, MAX_ATTEMPTS};
+use crate::joint::{add_outputs, JointDistribution};
+use crate::random::below;
+use crate::traits::{Chance, RollResult};
+use crate::weight::Weight;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A custom die with an outcome for each name on every face, where every
+/// face is equally likely
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomDie {
+    names: Vec<String>,
+    faces: Vec<Vec<RollResult>>,
+}
+
+/// Reasons that a record can't be rolled
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecordError {
+    /// the die has no faces
+    NoFaces,
+    /// a face doesn't have exactly one outcome for each name
+    Length {
+        face: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// two components have the same name
+    DuplicateName(String),
+    /// an expression uses a variable that isn't a component of the roll it
+    /// is computed from
+    UnboundVariable(String),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::NoFaces => write!(f, "die has no faces"),
+            RecordError::Length {
+                face,
+                expected,
+                found,
+            } => write!(
+                f,
+                "face {} has {} outcomes, but the die has {} names",
+                face, found, expected
+            ),
+            RecordError::DuplicateName(name) => write!(f, "name `{}` is used twice", name),
+            RecordError::UnboundVariable(name) => {
+                write!(f, "variable `{}` is not a component of the roll", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+/// the first name that appears twice
+fn check_names<S: AsRef<str>>(names: &[S]) -> Result<(), RecordError> {
+    match names.iter().enumerate().find(|(index, name)| {
+        names[..*index]
+            .iter()
+            .any(|other| other.as_ref() == name.as_ref())
+    }) {
+        Some((_, name)) => Err(RecordError::DuplicateName(String::from(name.as_ref()))),
+        None => Ok(()),
+    }
+}
+
+/// check that the names of the fields are different, and that every variable
+/// they use is one of `bound`
+fn check_fields(fields: &[(String, Expression)], bound: &[String]) -> Result<(), RecordError> {
+    let names: Vec<&String> = fields.iter().map(|(name, _)| name).collect();
+    check_names(&names)?;
+
+    match fields
+        .iter()
+        .flat_map(|(_, expr)| expr.unbound_variables())
+        .find(|name| !bound.iter().any(|bound| bound == name))
+    {
+        Some(name) => Err(RecordError::UnboundVariable(String::from(name))),
+        None => Ok(()),
+    }
+}
+
+impl CustomDie {
+    /// Create a custom die from the names of its components and the outcome
+    /// of each component on every face, checking that there is at least one
+    /// face and that every face has an outcome for each name
+    pub fn new(names: &[&str], faces: &[&[RollResult]]) -> Result<CustomDie, RecordError> {
+        if faces.is_empty() {
+            return Err(RecordError::NoFaces);
+        }
+        check_names(names)?;
+        if let Some((face, outcomes)) = faces
+            .iter()
+            .enumerate()
+            .find(|(_, outcomes)| outcomes.len() != names.len())
+        {
+            return Err(RecordError::Length {
+                face,
+                expected: names.len(),
+                found: outcomes.len(),
+            });
+        }
+
+        Ok(CustomDie {
+            names: names.iter().map(|name| String::from(*name)).collect(),
+            faces: faces.iter().map(|face| face.to_vec()).collect(),
+        })
+    }
+
+    /// the names of the components of every face
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// the outcome of each component on every face
+    pub fn faces(&self) -> &[Vec<RollResult>] {
+        &self.faces
+    }
+}
+
+/// Independent expressions, one for each name
+#[derive(Clone, Debug, PartialEq)]
+pub struct Components {
+    fields: Vec<(String, Expression)>,
+}
+
+impl Components {
+    /// Create independent components, checking that their names are
+    /// different and that they don't use any variable
+    pub fn new(fields: Vec<(String, Expression)>) -> Result<Components, RecordError> {
+        check_fields(&fields, &[])?;
+        Ok(Components { fields })
+    }
+
+    /// the name and expression of every component
+    pub fn fields(&self) -> &[(String, Expression)] {
+        &self.fields
+    }
+}
+
+/// New components computed from a roll, where every `Variable` is bound to
+/// the component of the roll with that name
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    record: Box<RecordExpression>,
+    fields: Vec<(String, Expression)>,
+}
+
+impl Mapping {
+    /// Create new components from a roll, checking that their names are
+    /// different and that every variable they use is a component of the roll
+    pub fn new(
+        record: RecordExpression,
+        fields: Vec<(String, Expression)>,
+    ) -> Result<Mapping, RecordError> {
+        check_fields(&fields, &record.names())?;
+        Ok(Mapping {
+            record: Box::new(record),
+            fields,
+        })
+    }
+
+    /// the roll that the components are computed from
+    pub fn record(&self) -> &RecordExpression {
+        &self.record
+    }
+
+    /// the name and expression of every new component
+    pub fn fields(&self) -> &[(String, Expression)] {
+        &self.fields
+    }
+}
+
+/// Represents a roll with several named outcomes
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecordExpression {
+    /// a single roll of a custom die
+    Die(CustomDie),
+    /// sum of `count` independent rolls, component by component
+    Repeat(u32, Box<RecordExpression>),
+    /// sum of two independent rolls, component by component. a component
+    /// that only one side has counts as zero on the other side
+    Sum(Box<RecordExpression>, Box<RecordExpression>),
+    /// independent expressions, one for each name
+    Fields(Components),
+    /// new components computed from a roll, where every `Variable` is bound
+    /// to the component of the roll with that name
+    Map(Mapping),
+}
+
+use RecordExpression::*;
+
+/// Add up several combinations of outcomes, component by component
+fn add_components(names: &[String], parts: &[(&[String], &[RollResult])]) -> Vec<RollResult> {
+    let mut outcome = vec![0; names.len()];
+    for (part_names, values) in parts {
+        for (name, value) in part_names.iter().zip(values.iter()) {
+            let index = names
+                .iter()
+                .position(|other| other == name)
+                .expect("every component must be named");
+            outcome[index] += value;
+        }
+    }
+
+    outcome
+}
+
+/// the names of both sides of a sum, in order of first appearance
+fn union(left: &[String], right: &[String]) -> Vec<String> {
+    let mut names = left.to_vec();
+    names.extend(right.iter().filter(|name| !left.contains(name)).cloned());
+
+    names
+}
+
+/// Find the joint distribution of the sum of two independent joint
+/// distributions, component by component
+fn convolve<W: Weight>(
+    left: &JointDistribution<W>,
+    right: &JointDistribution<W>,
+) -> JointDistribution<W> {
+    let names = union(left.names(), right.names());
+    let mut outcomes = vec![];
+    for (left_outcome, left_chance) in left.iter() {
+        for (right_outcome, right_chance) in right.iter() {
+            let outcome = add_components(
+                &names,
+                &[(left.names(), left_outcome), (right.names(), right_outcome)],
+            );
+            outcomes.push((outcome, left_chance.times(right_chance)));
+        }
+    }
+
+    JointDistribution::from_outcomes(names, outcomes)
+}
+
+/// Find the joint distribution of the sum of `count` independent copies of a
+/// joint distribution by exponentiation-by-squaring
+fn convolve_power<W: Weight>(base: &JointDistribution<W>, count: u32) -> JointDistribution<W> {
+    let names = base.names().to_vec();
+    let mut result =
+        JointDistribution::from_outcomes(names.clone(), vec![(vec![0; names.len()], W::one())]);
+    let mut base = base.clone();
+    let mut count = count;
+
+    while count > 0 {
+        if count & 1 == 1 {
+            result = convolve(&result, &base);
+        }
+        count >>= 1;
+        if count > 0 {
+            base = convolve(&base, &base);
+        }
+    }
+
+    result
+}
+
+impl RecordExpression {
+    /// Create a custom die from the names of its components and the outcome
+    /// of each component on every face, as described by `CustomDie::new`
+    pub fn die(names: &[&str], faces: &[&[RollResult]]) -> Result<RecordExpression, RecordError> {
+        CustomDie::new(names, faces).map(Die)
+    }
+
+    /// Create independent components, as described by `Components::new`
+    pub fn fields(fields: Vec<(String, Expression)>) -> Result<RecordExpression, RecordError> {
+        Components::new(fields).map(Fields)
+    }
+
+    /// Create new components from a roll, as described by `Mapping::new`
+    pub fn map(
+        record: RecordExpression,
+        fields: Vec<(String, Expression)>,
+    ) -> Result<RecordExpression, RecordError> {
+        Mapping::new(record, fields).map(Map)
+    }
+
+    /// the names of the components of this roll, in the order of its
+    /// outcomes
+    pub fn names(&self) -> Vec<String> {
+        match self {
+            Die(die) => die.names.clone(),
+            Repeat(_, expr) => expr.names(),
+            Sum(left, right) => union(&left.names(), &right.names()),
+            Fields(Components { fields }) | Map(Mapping { fields, .. }) => {
+                fields.iter().map(|(name, _)| name.clone()).collect()
+            }
+        }
+    }
+
+    /// whether this roll only counts outcomes that meet a condition
+    pub fn is_conditional(&self) -> bool {
+        match self {
+            Die(_) => false,
+            Repeat(_, expr) => expr.is_conditional(),
+            Sum(left, right) => left.is_conditional() || right.is_conditional(),
+            Fields(Components { fields }) => fields.iter().any(|(_, expr)| expr.is_conditional()),
+            Map(Mapping { record, fields }) => {
+                record.is_conditional() || fields.iter().any(|(_, expr)| expr.is_conditional())
+            }
+        }
+    }
+
+    /// Get a single outcome for each component of the roll expression, in
+    /// the order of `names`, or `UnmetCondition` if no roll met every
+    /// condition
+    ///
+    /// rolls that do not meet every condition are thrown out and rolled
+    /// again
+    pub fn roll(&self) -> Result<Vec<RollResult>, UnmetCondition> {
+        self.roll_with(&mut rand::thread_rng())
+    }
+
+    /// Get a single outcome for each component, using the given random
+    /// number generator
+    pub fn roll_with<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Result<Vec<RollResult>, UnmetCondition> {
+        (0..MAX_ATTEMPTS)
+            .filter_map(|_| self.roll_in(rng))
+            .next()
+            .ok_or(UnmetCondition)
+    }
+
+    /// Get a single outcome for each component, or `None` if a condition was
+    /// not met
+    fn roll_in<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<Vec<RollResult>> {
+        match self {
+            Die(die) => {
+                let face = below(die.faces.len() as u32, rng) as usize;
+                Some(die.faces[face].clone())
+            }
+            Repeat(count, expr) => {
+                let names = expr.names();
+                let mut outcome = vec![0; names.len()];
+                for _ in 0..*count {
+                    let roll = expr.roll_in(rng)?;
+                    outcome = add_components(&names, &[(&names, &outcome), (&names, &roll)]);
+                }
+                Some(outcome)
+            }
+            Sum(left, right) => {
+                let left_roll = left.roll_in(rng)?;
+                let right_roll = right.roll_in(rng)?;
+                Some(add_components(
+                    &self.names(),
+                    &[(&left.names(), &left_roll), (&right.names(), &right_roll)],
+                ))
+            }
+            Fields(Components { fields }) => fields
+                .iter()
+                .map(|(_, expr)| expr.roll_in(&Scope::new(), rng))
+                .collect(),
+            Map(Mapping { record, fields }) => {
+                let roll = record.roll_in(rng)?;
+                let scope: Scope = record.names().into_iter().zip(roll).collect();
+                fields
+                    .iter()
+                    .map(|(_, expr)| expr.roll_in(&scope, rng))
+                    .collect()
+            }
+        }
+    }
+
+    /// Create a joint distribution of all possible outcomes of every
+    /// component
+    pub fn plot(&self) -> JointDistribution<Chance> {
+        self.plot_as()
+    }
+
+    /// Create a joint distribution of all possible outcomes of every
+    /// component, with their likelihood represented by any kind of `Weight`
+    pub fn plot_as<W: Weight>(&self) -> JointDistribution<W> {
+        let plot = self.plot_in::<W>();
+        if !self.is_conditional() {
+            return plot;
+        }
+
+        plot.normalize()
+    }
+
+    /// Create a distribution of all possible outcomes of a single component
+    pub fn plot_component(&self, name: &str) -> Distribution<Chance> {
+        self.plot().marginal(name)
+    }
+
+    /// Create a joint distribution of all possible outcomes. Outcomes where a
+    /// condition is not met are left out, so the likelihoods may not add up
+    /// to a certainty.
+    fn plot_in<W: Weight>(&self) -> JointDistribution<W> {
+        match self {
+            Die(die) => {
+                let sides = die.faces.len() as u64;
+                JointDistribution::from_outcomes(
+                    die.names.clone(),
+                    die.faces
+                        .iter()
+                        .map(|face| (face.clone(), W::ratio(1, sides))),
+                )
+            }
+            Repeat(count, expr) => convolve_power(&expr.plot_in(), *count),
+            Sum(left, right) => convolve(&left.plot_in(), &right.plot_in()),
+            Fields(Components { fields }) => {
+                let mut table = BTreeMap::new();
+                add_outputs(fields, &Scope::new(), W::one(), &mut table);
+                JointDistribution::from_outcomes(self.names(), table)
+            }
+            Map(Mapping { record, fields }) => {
+                let plot = record.plot_in::<W>();
+                let mut table = BTreeMap::new();
+                for (outcome, chance) in plot.iter() {
+                    let scope: Scope = plot
+                        .names()
+                        .iter()
+                        .cloned()
+                        .zip(outcome.iter().cloned())
+                        .collect();
+                    add_outputs(fields, &scope, chance.clone(), &mut table);
+                }
+                JointDistribution::from_outcomes(self.names(), table)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::Comparison;
+    use crate::weight::Exact;
+
+    /// a Hero System normal damage die: STUN is the face, and BODY is 0 on a
+    /// 1, 2 on a 6 and 1 otherwise
+    fn normal_damage(count: u32) -> RecordExpression {
+        Repeat(
+            count,
+            Box::new(
+                RecordExpression::die(
+                    &["stun", "body"],
+                    &[&[1, 0], &[2, 1], &[3, 1], &[4, 1], &[5, 1], &[6, 2]],
+                )
+                .unwrap(),
+            ),
+        )
+    }
+
+    fn variable(name: &str) -> Box<Expression> {
+        Box::new(Expression::Variable(String::from(name)))
+    }
+
+    #[test]
+    fn components_come_from_the_same_dice() {
+        let plot = normal_damage(3).plot_as::<Exact>();
+
+        assert_eq!(plot.names(), &["stun", "body"]);
+        assert_eq!(
+            plot.marginal("stun"),
+            Expression::Dice { count: 3, sides: 6 }.plot_as()
+        );
+        assert_eq!(plot.chance(&[3, 0]), Exact::ratio(1, 216));
+        assert_eq!(plot.chance(&[18, 6]), Exact::ratio(1, 216));
+        // three 1s is the only way to roll no BODY
+        assert_eq!(plot.marginal("body").chance(0), Exact::ratio(1, 216));
+        assert_eq!(plot.chance(&[4, 0]), Exact::zero());
+        // each die adds 5/6 to the covariance
+        assert!((normal_damage(3).plot().covariance("stun", "body") - 2.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn faces_are_checked() {
+        assert_eq!(
+            RecordExpression::die(&["stun"], &[]),
+            Err(RecordError::NoFaces)
+        );
+        assert_eq!(
+            CustomDie::new(&["stun", "body"], &[&[1, 0], &[2]]),
+            Err(RecordError::Length {
+                face: 1,
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            CustomDie::new(&["body", "body"], &[&[1, 1]]),
+            Err(RecordError::DuplicateName(String::from("body")))
+        );
+    }
+
+    #[test]
+    fn fields_are_checked() {
+        let stun = || (String::from("stun"), Expression::Die(6));
+        assert_eq!(
+            RecordExpression::fields(vec![stun(), stun()]),
+            Err(RecordError::DuplicateName(String::from("stun")))
+        );
+        assert_eq!(
+            RecordExpression::fields(vec![(String::from("body"), *variable("stun"))]),
+            Err(RecordError::UnboundVariable(String::from("stun")))
+        );
+        assert_eq!(
+            RecordExpression::map(normal_damage(1), vec![stun(), stun()]),
+            Err(RecordError::DuplicateName(String::from("stun")))
+        );
+        assert_eq!(
+            RecordExpression::map(
+                normal_damage(1),
+                vec![(String::from("knockback"), *variable("knockback"))]
+            ),
+            Err(RecordError::UnboundVariable(String::from("knockback")))
+        );
+        // variables bound inside a field are fine
+        let bound = Expression::Let(
+            String::from("bonus"),
+            Box::new(Expression::Die(4)),
+            Box::new(Expression::Sum(variable("bonus"), variable("stun"))),
+        );
+        assert!(
+            RecordExpression::map(normal_damage(1), vec![(String::from("stun"), bound)]).is_ok()
+        );
+    }
+
+    #[test]
+    fn sum_keeps_every_component() {
+        let expression = Sum(
+            Box::new(normal_damage(1)),
+            Box::new(RecordExpression::die(&["body", "knockback"], &[&[1, 1], &[1, 2]]).unwrap()),
+        );
+
+        let plot = expression.plot_as::<Exact>();
+
+        assert_eq!(expression.names(), vec!["stun", "body", "knockback"]);
+        assert_eq!(plot.names(), &["stun", "body", "knockback"]);
+        assert_eq!(plot.chance(&[6, 3, 2]), Exact::ratio(1, 12));
+        assert_eq!(
+            plot.marginal("body"),
+            Distribution::new(
+                1,
+                vec![Exact::ratio(1, 6), Exact::ratio(4, 6), Exact::ratio(1, 6)]
+            )
+        );
+    }
+
+    #[test]
+    fn fields_are_independent() {
+        let expression = RecordExpression::fields(vec![
+            (String::from("attack"), Expression::Die(20)),
+            (String::from("damage"), Expression::Die(8)),
+        ])
+        .unwrap();
+
+        let plot = expression.plot();
+
+        assert_eq!(plot.iter().count(), 160);
+        assert!(plot.covariance("attack", "damage").abs() < 1e-9);
+    }
+
+    #[test]
+    fn map_computes_components() {
+        // whether 3d6 normal damage gets through 2 points of defense, and
+        // the STUN it deals
+        let expression = RecordExpression::map(
+            normal_damage(3),
+            vec![
+                (
+                    String::from("wounded"),
+                    Expression::Compare(
+                        variable("body"),
+                        Box::new(Expression::Constant(2)),
+                        Comparison::GreaterThan,
+                    ),
+                ),
+                (String::from("stun"), *variable("stun")),
+            ],
+        )
+        .unwrap();
+
+        let plot = expression.plot_as::<Exact>();
+
+        assert_eq!(plot.names(), &["wounded", "stun"]);
+        assert_eq!(plot.chance(&[1, 3]), Exact::zero());
+        assert_eq!(plot.chance(&[0, 3]), Exact::ratio(1, 216));
+        assert_eq!(plot.conditional("stun", "wounded", 1).max(), Some(18));
+    }
+
+    #[test]
+    fn conditional_fields_are_renormalized() {
+        let expression = RecordExpression::map(
+            normal_damage(1),
+            vec![(
+                String::from("body"),
+                Expression::Given(
+                    variable("body"),
+                    Box::new(Expression::Compare(
+                        variable("stun"),
+                        Box::new(Expression::Constant(1)),
+                        Comparison::GreaterThan,
+                    )),
+                ),
+            )],
+        )
+        .unwrap();
+
+        let plot = expression.plot_as::<Exact>();
+
+        assert_eq!(plot.marginal("body").min(), Some(1));
+        assert_eq!(plot.chance(&[2]), Exact::ratio(1, 5));
+        for _ in 0..100 {
+            assert_ne!(expression.roll(), Ok(vec![0]));
+        }
+
+        // a normal damage die never rolls 3 BODY
+        let impossible = RecordExpression::map(
+            normal_damage(1),
+            vec![(
+                String::from("body"),
+                Expression::Given(
+                    variable("body"),
+                    Box::new(Expression::Compare(
+                        variable("body"),
+                        Box::new(Expression::Constant(3)),
+                        Comparison::EqualTo,
+                    )),
+                ),
+            )],
+        )
+        .unwrap();
+        assert_eq!(impossible.roll(), Err(UnmetCondition));
+    }
+
+    #[test]
+    fn roll_matches_plot() {
+        let expression = Sum(
+            Box::new(normal_damage(2)),
+            Box::new(
+                RecordExpression::fields(vec![(String::from("stun"), Expression::Constant(1))])
+                    .unwrap(),
+            ),
+        );
+        let plot = expression.plot();
+
+        for _ in 0..1000 {
+            let roll = expression.roll().unwrap();
+            assert_eq!(roll.len(), 2);
+            assert!(plot.chance(&roll) > 0.0, "{:?}", roll);
+        }
+    }
+}
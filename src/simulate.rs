@@ -0,0 +1,399 @@
+//! Simulate Module
+//!
+//! Rolling an expression many times, to get an empirical histogram of its
+//! outcomes shaped like a plot. Useful for expressions that are too slow to
+//! plot exactly, and for checking plots against real rolls.
+//!
+//! An `Estimate` adds how far each likelihood and the mean could be from the
+//! exact plot, and `Expression::plot_within` only falls back to estimating
+//! when plotting exactly would cost too much.
+
+use crate::distribution::Distribution;
+use crate::expression::Expression;
+use crate::random::{seeded_rng, DiceRng, SEEDED_STREAM};
+use crate::traits::{Chance, PlotResult, RollResult, Rollable};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::thread;
+
+/// Number of times each outcome was rolled, stored sparsely so that rolls
+/// far apart don't count every outcome between them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Histogram {
+    /// number of rolls of each outcome that was rolled
+    counts: BTreeMap<RollResult, u64>,
+}
+
+impl Histogram {
+    /// count a single roll
+    pub(crate) fn add(&mut self, value: RollResult) {
+        *self.counts.entry(value).or_insert(0) += 1;
+    }
+
+    /// count every roll of another histogram
+    fn merge(&mut self, other: &Histogram) {
+        for (value, count) in &other.counts {
+            *self.counts.entry(*value).or_insert(0) += count;
+        }
+    }
+
+    /// the fraction of rolls of each outcome, with the number of rolls as
+    /// the total
+    pub(crate) fn to_plot(&self) -> PlotResult {
+        let total: u64 = self.counts.values().sum();
+
+        PlotResult {
+            total: total as f64,
+            plot: Distribution::from_outcomes(
+                self.counts
+                    .iter()
+                    .map(|(value, count)| (*value, *count as f64 / total as f64)),
+            ),
+        }
+    }
+}
+
+impl Expression {
+    /// Roll the expression `count` times, and get the fraction of rolls of
+    /// every outcome
+    ///
+    /// the result is shaped like `Rollable::plot`, except that `total` is the
+    /// number of rolls
+    pub fn roll_many(&self, count: u64) -> PlotResult {
+        // the generator of seeded rolls is much faster than `thread_rng`
+        self.roll_many_with(count, &mut seeded_rng(rand::thread_rng().gen()))
+    }
+
+    /// Roll the expression `count` times using the given random number
+    /// generator, and get the fraction of rolls of every outcome
+    ///
+    /// the expression is compiled into a `Program` first, which rolls the
+    /// same as the expression but faster. Rolling stops early if the
+    /// conditions of a roll can't be met, and the total counts only the
+    /// rolls that were made
+    pub fn roll_many_with<R: Rng + ?Sized>(&self, count: u64, rng: &mut R) -> PlotResult {
+        self.compile().roll_many_with(count, rng)
+    }
+
+    /// Roll the expression `count` times, split across `threads` threads,
+    /// and get the fraction of rolls of every outcome
+    pub fn roll_many_parallel(&self, count: u64, threads: usize) -> PlotResult {
+        self.roll_many_parallel_seeded(count, threads, rand::thread_rng().gen())
+    }
+
+    /// Roll the expression `count` times, split across `threads` threads,
+    /// and get the fraction of rolls of every outcome
+    ///
+    /// every thread rolls with the seed on its own PCG32 stream, starting
+    /// from the stream of `random::seeded_rng`, so the result is always the
+    /// same for the same seed and number of threads. With a single thread
+    /// it matches `roll_many_with(count, &mut seeded_rng(seed))`.
+    pub fn roll_many_parallel_seeded(&self, count: u64, threads: usize, seed: u64) -> PlotResult {
+        assert!(threads > 0, "can't roll with 0 threads");
+
+        let threads = threads as u64;
+        let program = &self.compile();
+        let histograms: Vec<Histogram> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|index| {
+                    // the first threads pick up the remainder
+                    let share = count / threads + u64::from(index < count % threads);
+                    let mut rng = DiceRng::new(seed, SEEDED_STREAM + index);
+                    scope.spawn(move || program.count_rolls(share, &mut rng))
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("rolling thread panicked"))
+                .collect()
+        });
+
+        let mut histogram = Histogram::default();
+        for other in &histograms {
+            histogram.merge(other);
+        }
+
+        histogram.to_plot()
+    }
+}
+
+/// The number of standard deviations covered by a 95% confidence interval
+const Z_95: f64 = 1.959_963_984_540_054;
+
+/// Bounds that contain a likelihood with 95% confidence
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Interval {
+    pub low: Chance,
+    pub high: Chance,
+}
+
+impl Interval {
+    /// the Wilson score interval of a likelihood, from the number of rolls
+    /// that landed on an outcome out of a number of rolls
+    pub fn wilson(hits: f64, rolls: f64) -> Interval {
+        if rolls == 0.0 {
+            return Interval {
+                low: 0.0,
+                high: 1.0,
+            };
+        }
+
+        let z2 = Z_95 * Z_95;
+        let fraction = hits / rolls;
+        let scale = 1.0 + z2 / rolls;
+        let center = (fraction + z2 / (2.0 * rolls)) / scale;
+        let spread = Z_95 / scale
+            * (fraction * (1.0 - fraction) / rolls + z2 / (4.0 * rolls * rolls)).sqrt();
+
+        Interval {
+            low: (center - spread).max(0.0),
+            high: (center + spread).min(1.0),
+        }
+    }
+
+    /// whether a likelihood is within the bounds
+    pub fn contains(&self, chance: Chance) -> bool {
+        self.low <= chance && chance <= self.high
+    }
+}
+
+/// An approximate plot of an expression, from rolling it many times
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Estimate {
+    /// the number of rolls
+    pub rolls: u64,
+    /// the fraction of rolls of every outcome
+    pub plot: Distribution,
+    /// the mean of the rolls
+    pub mean: f64,
+    /// the standard error of the mean, as the standard deviation of the
+    /// rolls over the square root of the number of rolls
+    pub standard_error: f64,
+}
+
+impl Estimate {
+    /// Create an estimate from the fraction of rolls of every outcome
+    fn from_plot(plot: PlotResult) -> Estimate {
+        let rolls = plot.total;
+        if rolls == 0.0 {
+            return Estimate {
+                rolls: 0,
+                plot: plot.plot,
+                mean: f64::NAN,
+                standard_error: f64::INFINITY,
+            };
+        }
+
+        // the sample variance, corrected for estimating the mean
+        let variance = plot.plot.variance() * rolls / (rolls - 1.0).max(1.0);
+
+        Estimate {
+            rolls: rolls as u64,
+            mean: plot.plot.mean(),
+            standard_error: (variance / rolls).sqrt(),
+            plot: plot.plot,
+        }
+    }
+
+    /// the 95% confidence interval of the likelihood of an outcome
+    pub fn interval(&self, value: RollResult) -> Interval {
+        let rolls = self.rolls as f64;
+        Interval::wilson((self.plot.chance(value) * rolls).round(), rolls)
+    }
+
+    /// Iterate over every outcome between the lowest and highest outcome
+    /// rolled, along with the 95% confidence interval of its likelihood
+    pub fn intervals<'a>(&'a self) -> impl Iterator<Item = (RollResult, Interval)> + 'a {
+        self.plot
+            .iter()
+            .map(move |(value, _)| (value, self.interval(value)))
+    }
+
+    /// the 95% confidence interval of the mean
+    pub fn mean_interval(&self) -> (f64, f64) {
+        (
+            self.mean - Z_95 * self.standard_error,
+            self.mean + Z_95 * self.standard_error,
+        )
+    }
+}
+
+/// A plot of an expression, either exact or estimated
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Analysis {
+    Exact(PlotResult),
+    Estimated(Estimate),
+}
+
+impl Analysis {
+    /// the likelihood of every outcome
+    pub fn plot(&self) -> &Distribution {
+        match self {
+            Analysis::Exact(plot) => &plot.plot,
+            Analysis::Estimated(estimate) => &estimate.plot,
+        }
+    }
+
+    /// whether the likelihoods are exact
+    pub fn is_exact(&self) -> bool {
+        match self {
+            Analysis::Exact(_) => true,
+            Analysis::Estimated(_) => false,
+        }
+    }
+}
+
+impl Expression {
+    /// Estimate the likelihood of every outcome by rolling the expression
+    /// `rolls` times
+    pub fn estimate(&self, rolls: u64) -> Estimate {
+        Estimate::from_plot(self.roll_many(rolls))
+    }
+
+    /// Estimate the likelihood of every outcome by rolling the expression
+    /// `rolls` times, using the given random number generator
+    pub fn estimate_with<R: Rng + ?Sized>(&self, rolls: u64, rng: &mut R) -> Estimate {
+        Estimate::from_plot(self.roll_many_with(rolls, rng))
+    }
+
+    /// Plot the expression exactly if `Expression::plot_cost` is within the
+    /// budget, or estimate it by rolling it `rolls` times otherwise
+    pub fn plot_within(&self, budget: f64, rolls: u64) -> Analysis {
+        if self.plot_cost() <= budget {
+            Analysis::Exact(self.plot())
+        } else {
+            Analysis::Estimated(self.estimate(rolls))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_counts_each_outcome() {
+        let mut histogram = Histogram::default();
+        for value in &[3, 1, 5, 3, -2] {
+            histogram.add(*value);
+        }
+
+        assert_eq!(
+            histogram.counts.clone().into_iter().collect::<Vec<_>>(),
+            vec![(-2, 1), (1, 1), (3, 2), (5, 1)]
+        );
+
+        let mut merged = Histogram::default();
+        merged.add(10);
+        merged.merge(&histogram);
+        assert_eq!(merged.counts.values().sum::<u64>(), 6);
+        assert_eq!(merged.counts[&10], 1);
+        assert_eq!(merged.counts[&3], 2);
+        assert_eq!(merged.to_plot().plot.chance(3), 2.0 / 6.0);
+
+        // only the outcomes that were rolled are counted
+        let mut far = Histogram::default();
+        far.add(RollResult::MIN);
+        far.add(RollResult::MAX);
+        assert_eq!(far.counts.len(), 2);
+    }
+
+    #[test]
+    fn histogram_is_close_to_plot() {
+        let expression = crate::parse("2d6").unwrap();
+
+        let actual = expression.roll_many(60_000);
+
+        assert_eq!(actual.total, 60_000.0);
+        assert_eq!(actual.plot.min(), Some(2));
+        assert_eq!(actual.plot.max(), Some(12));
+        let expected = expression.plot().plot;
+        for (value, chance) in expected.iter() {
+            assert!(
+                (actual.plot.chance(value) - chance).abs() < 0.01,
+                "{}: expected {}, got {}",
+                value,
+                chance,
+                actual.plot.chance(value)
+            );
+        }
+    }
+
+    #[test]
+    fn parallel_rolls_are_repeatable() {
+        let expression = crate::parse("let a = d20 in a + d6 | a > 10").unwrap();
+
+        let actual = expression.roll_many_parallel_seeded(10_001, 4, 42);
+
+        assert_eq!(actual.total, 10_001.0);
+        assert_eq!(actual.plot.min(), Some(12));
+        assert_eq!(actual, expression.roll_many_parallel_seeded(10_001, 4, 42));
+        assert_eq!(expression.roll_many_parallel(10, 3).total, 10.0);
+    }
+
+    #[test]
+    fn single_thread_matches_seeded_rolls() {
+        let expression = crate::parse("adv(d20) + 3").unwrap();
+
+        assert_eq!(
+            expression.roll_many_parallel_seeded(1000, 1, 7),
+            expression.roll_many_with(1000, &mut seeded_rng(7))
+        );
+    }
+
+    #[test]
+    fn no_rolls_are_empty() {
+        let actual = Expression::Die(6).roll_many(0);
+
+        assert_eq!(actual.total, 0.0);
+        assert!(actual.plot.is_empty());
+    }
+
+    #[test]
+    fn wilson_interval() {
+        // 8 successes out of 10, from Wilson's method
+        let interval = Interval::wilson(8.0, 10.0);
+        assert!((interval.low - 0.4902).abs() < 1e-4, "{:?}", interval);
+        assert!((interval.high - 0.9433).abs() < 1e-4, "{:?}", interval);
+
+        // outcomes that were never rolled could still be possible
+        let never = Interval::wilson(0.0, 100.0);
+        assert!(never.low < 1e-12);
+        assert!(never.high > 0.0 && never.high < 0.05);
+    }
+
+    #[test]
+    fn estimate_covers_plot() {
+        let expression = crate::parse("3d6 + d4").unwrap();
+        let exact = expression.plot().plot;
+
+        let estimate = expression.estimate_with(20_000, &mut seeded_rng(3));
+
+        assert_eq!(estimate.rolls, 20_000);
+        let (low, high) = estimate.mean_interval();
+        assert!(low < exact.mean() && exact.mean() < high);
+        assert!(
+            (estimate.standard_error - exact.standard_deviation() / 20_000f64.sqrt()).abs() < 1e-3
+        );
+        let covered = estimate
+            .intervals()
+            .filter(|(value, interval)| interval.contains(exact.chance(*value)))
+            .count();
+        // about 95% of intervals should contain the exact likelihood
+        assert!(covered >= 18, "{} of 19 covered", covered);
+    }
+
+    #[test]
+    fn expensive_plots_are_estimated() {
+        let cheap = crate::parse("2d6 + 3").unwrap();
+        let expensive = crate::parse("2000d100 + d6").unwrap();
+
+        assert!(cheap.plot_cost() < 1e3);
+        assert!(expensive.plot_cost() > 1e9);
+        assert!(cheap.plot_within(1e6, 1000).is_exact());
+        let analysis = expensive.plot_within(1e6, 1000);
+        assert!(!analysis.is_exact());
+        assert!(analysis.plot().min().unwrap() > 2000);
+    }
+}
@@ -0,0 +1,229 @@
+//! Statistics Module
+
+use crate::distribution::Distribution;
+use crate::traits::{Chance, RollResult, EPSILON};
+
+/// Summary statistics of a distribution
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Statistics {
+    pub mean: f64,
+    pub variance: f64,
+    pub standard_deviation: f64,
+    pub median: Option<RollResult>,
+    pub modes: Vec<RollResult>,
+    pub skewness: f64,
+    pub excess_kurtosis: f64,
+}
+
+impl Distribution<Chance> {
+    /// the expected value of an outcome
+    pub fn mean(&self) -> f64 {
+        self.iter()
+            .map(|(value, chance)| f64::from(value) * chance)
+            .sum::<f64>()
+            / self.mass()
+    }
+
+    /// the expected value of the `order`th power of the distance between an
+    /// outcome and the mean
+    pub fn central_moment(&self, order: i32) -> f64 {
+        let mean = self.mean();
+        self.iter()
+            .map(|(value, chance)| (f64::from(value) - mean).powi(order) * chance)
+            .sum::<f64>()
+            / self.mass()
+    }
+
+    /// the expected squared distance between an outcome and the mean
+    pub fn variance(&self) -> f64 {
+        self.central_moment(2)
+    }
+
+    /// the square root of the variance
+    pub fn standard_deviation(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// whether the outcomes are too close together to have a shape, like
+    /// a single outcome or none at all
+    fn has_no_spread(&self) -> bool {
+        let variance = self.variance();
+        variance.is_nan() || variance <= 0.0
+    }
+
+    /// how lopsided the distribution is: positive when the tail of high
+    /// outcomes is longer, and negative when the tail of low outcomes is
+    /// longer. 0 when there is no spread
+    pub fn skewness(&self) -> f64 {
+        if self.has_no_spread() {
+            return 0.0;
+        }
+        self.central_moment(3) / self.variance().powf(1.5)
+    }
+
+    /// how heavy the tails of the distribution are, where a normal
+    /// distribution has a kurtosis of 3. also 3 when there is no spread, so
+    /// that the excess kurtosis is 0
+    pub fn kurtosis(&self) -> f64 {
+        if self.has_no_spread() {
+            return 3.0;
+        }
+        self.central_moment(4) / self.variance().powi(2)
+    }
+
+    /// the kurtosis relative to a normal distribution
+    pub fn excess_kurtosis(&self) -> f64 {
+        self.kurtosis() - 3.0
+    }
+
+    /// the lowest outcome that is at least as likely as `fraction` to be
+    /// rolled over
+    ///
+    /// `fraction` is between 0 and 1, e.g. `0.9` for the 90th percentile
+    pub fn quantile(&self, fraction: f64) -> Option<RollResult> {
+        let mass = self.mass();
+        let mut cumulative = 0.0;
+
+        for (value, chance) in self.iter() {
+            cumulative += chance / mass;
+            if chance != 0.0 && cumulative >= fraction - EPSILON {
+                return Some(value);
+            }
+        }
+
+        self.max()
+    }
+
+    /// the lowest outcome with at least half of the outcomes at or below it
+    pub fn median(&self) -> Option<RollResult> {
+        self.quantile(0.5)
+    }
+
+    /// the most likely outcomes, in order
+    pub fn modes(&self) -> Vec<RollResult> {
+        let highest = self
+            .chances()
+            .iter()
+            .cloned()
+            .fold(0.0, |highest: f64, chance| highest.max(chance));
+
+        self.iter()
+            .filter(|(_, chance)| highest - chance <= EPSILON * highest)
+            .map(|(value, _)| value)
+            .collect()
+    }
+
+    /// Find all summary statistics of this distribution
+    pub fn statistics(&self) -> Statistics {
+        Statistics {
+            mean: self.mean(),
+            variance: self.variance(),
+            standard_deviation: self.standard_deviation(),
+            median: self.median(),
+            modes: self.modes(),
+            skewness: self.skewness(),
+            excess_kurtosis: self.excess_kurtosis(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::{Comparison, Expression};
+    use crate::test_helpers::assert_close;
+    use crate::traits::Rollable;
+
+    #[test]
+    fn single_die_statistics() {
+        let plot = Expression::Die(6).plot().plot;
+
+        assert_close(3.5, plot.mean());
+        assert_close(35.0 / 12.0, plot.variance());
+        assert_close((35.0f64 / 12.0).sqrt(), plot.standard_deviation());
+        assert_close(0.0, plot.skewness());
+        assert_close(-222.0 / 175.0, plot.excess_kurtosis());
+        assert_eq!(plot.median(), Some(3));
+        assert_eq!(plot.modes(), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn dice_pool_statistics() {
+        let plot = Expression::Dice { count: 2, sides: 6 }.plot().plot;
+
+        assert_close(7.0, plot.mean());
+        assert_close(35.0 / 6.0, plot.variance());
+        assert_close(0.0, plot.skewness());
+        // excess kurtosis of a sum shrinks with the number of dice
+        assert_close(-111.0 / 175.0, plot.excess_kurtosis());
+        assert_eq!(plot.median(), Some(7));
+        assert_eq!(plot.modes(), vec![7]);
+    }
+
+    #[test]
+    fn advantage_statistics() {
+        let plot = Expression::Advantage(Box::new(Expression::Die(20)))
+            .plot()
+            .plot;
+
+        assert_close(13.825, plot.mean());
+        assert_eq!(plot.median(), Some(15));
+        assert_eq!(plot.modes(), vec![20]);
+        assert!(plot.skewness() < 0.0);
+    }
+
+    #[test]
+    fn bernoulli_statistics() {
+        // rolling a 6 on a d6 is a bernoulli trial with p = 1/6
+        let plot = Expression::Count(Comparison::EqualTo, 6, 1, 6).plot().plot;
+        let p: f64 = 1.0 / 6.0;
+
+        assert_close(p, plot.mean());
+        assert_close(p * (1.0 - p), plot.variance());
+        assert_close((1.0 - 2.0 * p) / (p * (1.0 - p)).sqrt(), plot.skewness());
+        assert_close(
+            (1.0 - 6.0 * p * (1.0 - p)) / (p * (1.0 - p)),
+            plot.excess_kurtosis(),
+        );
+        assert_eq!(plot.modes(), vec![0]);
+    }
+
+    #[test]
+    fn constant_statistics() {
+        let actual = Expression::Constant(3).plot().plot.statistics();
+
+        assert_close(3.0, actual.mean);
+        assert_close(0.0, actual.variance);
+        assert_eq!(actual.skewness, 0.0);
+        assert_eq!(actual.excess_kurtosis, 0.0);
+        assert_eq!(actual.modes, vec![3]);
+        // NaN would be written as `null`
+        assert!(!serde_json::to_string(&actual).unwrap().contains("null"));
+        assert_eq!(Distribution::<Chance>::empty().skewness(), 0.0);
+    }
+
+    #[test]
+    fn quantiles() {
+        let plot = Expression::Dice { count: 3, sides: 6 }.plot().plot;
+
+        assert_eq!(plot.quantile(0.0), Some(3));
+        // 1 of 216 outcomes is a 3
+        assert_eq!(plot.quantile(1.0 / 216.0), Some(3));
+        assert_eq!(plot.quantile(2.0 / 216.0), Some(4));
+        assert_eq!(plot.quantile(0.5), Some(10));
+        assert_eq!(plot.quantile(1.0), Some(18));
+        assert_eq!(Distribution::<Chance>::empty().quantile(0.5), None);
+    }
+
+    #[test]
+    fn statistics_summary() {
+        let plot = Expression::Die(4).plot().plot;
+
+        let actual = plot.statistics();
+
+        assert_close(2.5, actual.mean);
+        assert_close(1.25, actual.variance);
+        assert_eq!(actual.median, Some(2));
+        assert_eq!(actual.modes, vec![1, 2, 3, 4]);
+    }
+}
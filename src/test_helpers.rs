@@ -0,0 +1,14 @@
+#[cfg(test)]
+pub fn assert_close(expected: f64, actual: f64) {
+    assert_within(expected, actual, 1e-9);
+}
+
+#[cfg(test)]
+pub fn assert_within(expected: f64, actual: f64, tolerance: f64) {
+    assert!(
+        (expected - actual).abs() < tolerance,
+        "expected {}, got {}",
+        expected,
+        actual
+    );
+}
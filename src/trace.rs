@@ -0,0 +1,282 @@
+//! Trace Module
+//!
+//! A record of a single roll, with every die that was rolled and the outcome
+//! of every part of the expression, e.g. `4d6 → [6, 5, 3, 1] = 15`.
+
+use crate::expression::{pick_option, roll_die, Expression, Scope, UnmetCondition, MAX_ATTEMPTS};
+use crate::traits::RollResult;
+use rand::Rng;
+use std::fmt;
+
+use Expression::*;
+
+/// A single roll of an expression, mirroring the structure of the expression
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trace {
+    /// the part of the expression that was rolled, e.g. `4d6`
+    pub expression: String,
+    /// every die rolled directly by this part of the expression, in order
+    pub dice: Vec<RollResult>,
+    /// the outcome of this part of the expression
+    pub value: RollResult,
+    /// whether this outcome was thrown away, like the lower roll of `adv`
+    pub dropped: bool,
+    /// the rolls of the parts of this expression
+    pub children: Vec<Trace>,
+}
+
+impl Trace {
+    fn new(expression: &Expression, value: RollResult) -> Trace {
+        Trace {
+            expression: expression.to_string(),
+            dice: vec![],
+            value,
+            dropped: false,
+            children: vec![],
+        }
+    }
+
+    fn with_dice(mut self, dice: Vec<RollResult>) -> Trace {
+        self.dice = dice;
+        self
+    }
+
+    fn with_children(mut self, children: Vec<Trace>) -> Trace {
+        self.children = children;
+        self
+    }
+
+    /// whether this trace has anything to show beyond its outcome, such as
+    /// the roll of a constant
+    fn is_trivial(&self) -> bool {
+        self.dice.is_empty() && self.children.is_empty()
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{:indent$}{}", "", self.expression, indent = depth * 2)?;
+        if !self.dice.is_empty() {
+            let dice: Vec<String> = self.dice.iter().map(|die| die.to_string()).collect();
+            write!(f, " → [{}]", dice.join(", "))?;
+        }
+        write!(f, " = {}", self.value)?;
+        if self.dropped {
+            write!(f, " (dropped)")?;
+        }
+
+        for child in self.children.iter().filter(|child| !child.is_trivial()) {
+            writeln!(f)?;
+            child.fmt_indented(f, depth + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Trace {
+    /// write one line for every part of the roll that rolled dice, indented
+    /// under the part that contains it
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+/// roll `count` dice with `sides` faces
+fn roll_dice<R: Rng + ?Sized>(count: u32, sides: u32, rng: &mut R) -> Vec<RollResult> {
+    (0..count).map(|_| roll_die(sides, rng)).collect()
+}
+
+impl Expression {
+    /// Get a single value from the roll expression, along with every die
+    /// that was rolled and the outcome of every part of the expression
+    ///
+    /// rolls that do not meet every condition are thrown out and rolled
+    /// again, failing with `UnmetCondition` if none of them do
+    pub fn roll_detailed(&self) -> Result<Trace, UnmetCondition> {
+        self.roll_detailed_with(&mut rand::thread_rng())
+    }
+
+    /// Get a single value from the roll expression, along with every die
+    /// that was rolled, using the given random number generator
+    pub fn roll_detailed_with<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Result<Trace, UnmetCondition> {
+        (0..MAX_ATTEMPTS)
+            .filter_map(|_| self.trace_in(&Scope::new(), rng))
+            .next()
+            .ok_or(UnmetCondition)
+    }
+
+    /// Roll the expression with variables bound to the given outcomes, or
+    /// `None` if a condition was not met
+    fn trace_in<R: Rng + ?Sized>(&self, scope: &Scope, rng: &mut R) -> Option<Trace> {
+        match self {
+            Constant(value) => Some(Trace::new(self, *value)),
+            Die(sides) => {
+                let dice = roll_dice(1, *sides, rng);
+                Some(Trace::new(self, dice[0]).with_dice(dice))
+            }
+            Dice { count, sides } => {
+                let dice = roll_dice(*count, *sides, rng);
+                Some(Trace::new(self, dice.iter().sum()).with_dice(dice))
+            }
+            Count(comparison, face, count, sides) => {
+                let matches = comparison.operator();
+                let dice = roll_dice(*count, *sides, rng);
+                let hits = dice.iter().filter(|roll| matches(roll, face) == 1).count();
+                Some(Trace::new(self, hits as RollResult).with_dice(dice))
+            }
+            Variable(name) => {
+                let value = scope
+                    .get(name)
+                    .unwrap_or_else(|| panic!("unbound variable `{}`", name));
+                Some(Trace::new(self, *value))
+            }
+            Query(_, expr) | Label(_, expr) => {
+                let child = expr.trace_in(scope, rng)?;
+                Some(Trace::new(self, child.value).with_children(vec![child]))
+            }
+            Let(name, value, body) => {
+                let value = value.trace_in(scope, rng)?;
+                let mut scope = scope.clone();
+                scope.insert(name.clone(), value.value);
+                let body = body.trace_in(&scope, rng)?;
+                Some(Trace::new(self, body.value).with_children(vec![value, body]))
+            }
+            Given(expr, condition) => {
+                let expr = expr.trace_in(scope, rng)?;
+                let condition = condition.trace_in(scope, rng)?;
+                if condition.value == 0 {
+                    return None;
+                }
+                Some(Trace::new(self, expr.value).with_children(vec![expr, condition]))
+            }
+            Choice(options) => {
+                let option = pick_option(options, rng).trace_in(scope, rng)?;
+                Some(Trace::new(self, option.value).with_children(vec![option]))
+            }
+            _ => {
+                let (operator, left, right) = self
+                    .get_operation()
+                    .expect("expression does not represent an operation");
+                let mut left = left.trace_in(scope, rng)?;
+                let mut right = right.trace_in(scope, rng)?;
+                let value = (operator)(&left.value, &right.value);
+
+                // only one of the rolls is kept by advantage and disadvantage
+                if let Advantage(_) | Disadvantage(_) = self {
+                    if value == left.value {
+                        right.dropped = true;
+                    } else {
+                        left.dropped = true;
+                    }
+                }
+
+                Some(Trace::new(self, value).with_children(vec![left, right]))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::Comparison;
+
+    #[test]
+    fn dice_are_recorded() {
+        let expression = Expression::Dice { count: 4, sides: 6 };
+
+        let actual = expression.roll_detailed().unwrap();
+
+        assert_eq!(actual.expression, "4d6");
+        assert_eq!(actual.dice.len(), 4);
+        assert!(actual.dice.iter().all(|die| (1..=6).contains(die)));
+        assert_eq!(actual.value, actual.dice.iter().sum::<RollResult>());
+        let dice: Vec<String> = actual.dice.iter().map(|die| die.to_string()).collect();
+        assert_eq!(
+            actual.to_string(),
+            format!("4d6 → [{}] = {}", dice.join(", "), actual.value)
+        );
+    }
+
+    #[test]
+    fn tree_mirrors_expression() {
+        let expression = crate::parse("2d6[fire] + d8 * 2").unwrap();
+
+        let actual = expression.roll_detailed().unwrap();
+
+        assert_eq!(actual.expression, "2d6[fire] + d8 * 2");
+        let (fire, slashing) = (&actual.children[0], &actual.children[1]);
+        assert_eq!(fire.expression, "2d6[fire]");
+        assert_eq!(fire.children[0].dice.len(), 2);
+        assert_eq!(slashing.children[1].expression, "2");
+        assert_eq!(fire.value, fire.children[0].dice.iter().sum::<RollResult>());
+        assert_eq!(actual.value, fire.value + slashing.children[0].value * 2);
+
+        // constants have nothing to show
+        let text = actual.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[4].starts_with("    d8 → ["));
+    }
+
+    #[test]
+    fn lower_roll_of_advantage_is_dropped() {
+        let expression = Expression::Advantage(Box::new(Expression::Die(20)));
+
+        for _ in 0..20 {
+            let actual = expression.roll_detailed().unwrap();
+
+            let kept: Vec<&Trace> = actual.children.iter().filter(|c| !c.dropped).collect();
+            assert_eq!(kept.len(), 1);
+            assert_eq!(kept[0].value, actual.value);
+            assert!(actual
+                .children
+                .iter()
+                .all(|child| child.value <= actual.value));
+        }
+    }
+
+    #[test]
+    fn count_records_every_die() {
+        let expression = Expression::Count(Comparison::GreaterThanOrEqualTo, 5, 6, 6);
+
+        let actual = expression.roll_detailed().unwrap();
+
+        assert_eq!(actual.dice.len(), 6);
+        assert_eq!(
+            actual.value,
+            actual.dice.iter().filter(|die| **die >= 5).count() as RollResult
+        );
+    }
+
+    #[test]
+    fn conditions_are_met() {
+        let expression = crate::parse("let a = d20 in a | a > 10").unwrap();
+
+        for _ in 0..20 {
+            let actual = expression.roll_detailed().unwrap();
+
+            assert!(actual.value > 10);
+            assert_eq!(actual.children[0].value, actual.value);
+        }
+    }
+
+    #[test]
+    fn impossible_conditions_fail() {
+        let expression = crate::parse("let a = d4 in a | a > 10").unwrap();
+
+        assert_eq!(expression.roll_detailed(), Err(UnmetCondition));
+    }
+
+    #[test]
+    fn serializes_for_the_web() {
+        let trace = Trace::new(&Expression::Die(6), 4).with_dice(vec![4]);
+
+        assert_eq!(
+            serde_json::to_string(&trace).unwrap(),
+            r#"{"expression":"d6","dice":[4],"value":4,"dropped":false,"children":[]}"#
+        );
+    }
+}
@@ -1,25 +1,60 @@
+use crate::distribution::Distribution;
+use crate::random::seeded_rng;
+use rand::Rng;
 use std::collections::HashMap;
 
 /// The result of a roll
 pub type RollResult = i32;
 
 /// The likelihood of a certain roll result
-pub type Chance = f32;
+pub type Chance = f64;
 
-/// table of the percent likelihood of possible outcomes
+/// Margin for floating point error when comparing likelihoods
+pub(crate) const EPSILON: Chance = 1e-12;
+
+/// table of the percent likelihood of possible outcomes, as exported by
+/// `Distribution::to_table`
 pub type PlotTable = HashMap<RollResult, Chance>;
 
 /// Plot for a roll expression
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct PlotResult {
-    /// total number of possible outcomes
-    pub total: f32,
+    /// total number of possible outcomes, counting every roll of every die
+    /// as a separate outcome. for a choice, this is the total weight times
+    /// the outcomes of every option with weight, so that each option is a
+    /// whole number of them. outcomes where a condition is not met are
+    /// counted too, and a plot from rolls counts the rolls
+    pub total: f64,
     /// likelihood of all possible outcomes in this roll
-    pub plot: PlotTable,
+    pub plot: Distribution,
 }
 
 /// Common trait for a roll expression
+///
+/// rolling is generic over the random number generator, so only `plot` can be
+/// called on a `dyn Rollable`
 pub trait Rollable {
-    fn roll(&self) -> RollResult;
+    /// Get a single value, using the given random number generator
+    fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> RollResult
+    where
+        Self: Sized;
+
+    /// Get a single value
+    fn roll(&self) -> RollResult
+    where
+        Self: Sized,
+    {
+        self.roll_with(&mut rand::thread_rng())
+    }
+
+    /// Get a single value that is always the same for the same seed and
+    /// `random::STREAM_VERSION`, on every platform
+    fn roll_seeded(&self, seed: u64) -> RollResult
+    where
+        Self: Sized,
+    {
+        self.roll_with(&mut seeded_rng(seed))
+    }
+
     fn plot(&self) -> PlotResult;
 }
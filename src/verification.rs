@@ -0,0 +1,593 @@
+//! Verification Module
+//!
+//! Statistical checks that rolling an expression agrees with its plot. An
+//! expression is rolled many times, and a chi-square goodness of fit test
+//! measures how likely the counts of each outcome would be if the plot were
+//! right. A tiny p-value means that `roll` and `plot` disagree.
+//!
+//! The same tests can check real rolls, such as a log of the rolls of a
+//! suspicious d20, against the plot of the expression that was rolled.
+
+use crate::distribution::Distribution;
+use crate::expression::Expression;
+use crate::random::seeded_rng;
+use crate::traits::{Chance, PlotResult, RollResult, Rollable};
+use rand::Rng;
+use std::collections::BTreeMap;
+
+/// The lowest number of rolls expected in each group of outcomes, below
+/// which the chi-square test is not reliable
+const MIN_EXPECTED: f64 = 5.0;
+
+/// Relative precision of the incomplete gamma function
+const PRECISION: f64 = 1e-14;
+
+/// The result of a chi-square goodness of fit test
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GoodnessOfFit {
+    /// the sum of the squared difference between the observed and expected
+    /// number of rolls of each group of outcomes, over the expected number
+    pub statistic: f64,
+    pub degrees_of_freedom: u32,
+    /// the chance of a statistic at least this large if the rolls followed
+    /// the expected distribution
+    pub p_value: f64,
+}
+
+impl GoodnessOfFit {
+    /// whether the rolls are consistent with the expected distribution at
+    /// the given significance level, such as `0.01`
+    pub fn fits(&self, significance: f64) -> bool {
+        self.p_value > significance
+    }
+}
+
+/// The result of a Kolmogorov-Smirnov test
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KolmogorovSmirnov {
+    /// the largest distance between the observed and expected chance of
+    /// rolling at most each outcome
+    pub statistic: f64,
+    /// the chance of a statistic at least this large if the rolls followed
+    /// the expected distribution
+    pub p_value: f64,
+}
+
+/// How far the number of rolls of an outcome is from what was expected
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Deviation {
+    pub value: RollResult,
+    pub observed: u64,
+    pub expected: f64,
+    /// the difference between the observed and expected number of rolls,
+    /// in standard deviations of a chi-square test
+    pub residual: f64,
+}
+
+/// Tests of whether real rolls of an expression are consistent with its plot
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FairnessReport {
+    /// the number of rolls
+    pub rolls: u64,
+    pub chi_square: GoodnessOfFit,
+    pub kolmogorov_smirnov: KolmogorovSmirnov,
+    /// the outcome that was rolled most often compared to the plot
+    pub most_over_represented: Option<Deviation>,
+    /// the outcome that was rolled least often compared to the plot
+    pub most_under_represented: Option<Deviation>,
+    /// every rolled outcome that the plot says is impossible, with the
+    /// number of times it was rolled
+    pub impossible: Vec<(RollResult, u64)>,
+}
+
+/// the natural logarithm of the gamma function, by the Lanczos
+/// approximation. `x` must be at least 0.5
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    let x = x - 1.0;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (index, coefficient)| {
+            sum + coefficient / (x + index as f64 + 1.0)
+        });
+    let t = x + 7.5;
+
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// the regularized upper incomplete gamma function `Q(a, x)`
+fn upper_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let scale = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        // the series of the lower function converges quickly
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        let mut denominator = a;
+        while term.abs() > sum.abs() * PRECISION {
+            denominator += 1.0;
+            term *= x / denominator;
+            sum += term;
+        }
+        return (1.0 - sum * scale).max(0.0);
+    }
+
+    // otherwise use the continued fraction, by the modified Lentz method
+    let tiny = f64::MIN_POSITIVE / PRECISION;
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / tiny;
+    let mut d = 1.0 / b;
+    let mut fraction = d;
+    for step in 1.. {
+        let step = f64::from(step);
+        let an = -step * (step - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < tiny {
+            d = tiny;
+        }
+        c = b + an / c;
+        if c.abs() < tiny {
+            c = tiny;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        fraction *= delta;
+        if (delta - 1.0).abs() < PRECISION {
+            break;
+        }
+    }
+
+    fraction * scale
+}
+
+/// the chance that a chi-square distributed statistic with the given degrees
+/// of freedom is at least `statistic`
+pub fn chi_square_p_value(statistic: f64, degrees_of_freedom: u32) -> f64 {
+    if degrees_of_freedom == 0 {
+        return 1.0;
+    }
+    if statistic.is_infinite() {
+        return 0.0;
+    }
+
+    upper_gamma(f64::from(degrees_of_freedom) / 2.0, statistic / 2.0)
+}
+
+/// the chance that the Kolmogorov-Smirnov statistic of `rolls` rolls is at
+/// least `statistic`, by the asymptotic Kolmogorov distribution with
+/// Stephens' correction for small samples
+fn kolmogorov_smirnov_p_value(statistic: f64, rolls: f64) -> f64 {
+    let root = rolls.sqrt();
+    let lambda = (root + 0.12 + 0.11 / root) * statistic;
+    // the series converges too slowly to be useful, and the result is 1
+    if lambda < 0.2 {
+        return 1.0;
+    }
+
+    let mut sum = 0.0;
+    let mut sign = 1.0;
+    for step in 1..=100 {
+        let term = sign * (-2.0 * f64::from(step * step) * lambda * lambda).exp();
+        sum += term;
+        if term.abs() <= PRECISION * sum.abs() {
+            break;
+        }
+        sign = -sign;
+    }
+
+    (2.0 * sum).clamp(0.0, 1.0)
+}
+
+/// Test whether rolls, as returned by `Expression::roll_many`, are consistent
+/// with an expected distribution
+///
+/// neighbouring outcomes are grouped until each group is expected to be
+/// rolled at least 5 times. Rolling an impossible outcome always fails.
+pub fn goodness_of_fit(observed: &PlotResult, expected: &Distribution) -> GoodnessOfFit {
+    let rolls = observed.total;
+    let counts = observed
+        .plot
+        .iter()
+        .map(|(value, chance): (RollResult, Chance)| (value, (chance * rolls).round()))
+        .filter(|(_, count)| *count > 0.0)
+        .collect();
+
+    fit_counts(&counts, rolls, expected)
+}
+
+/// the chi-square test of the number of times each outcome was rolled,
+/// out of `rolls` rolls
+fn fit_counts(
+    counts: &BTreeMap<RollResult, f64>,
+    rolls: f64,
+    expected: &Distribution,
+) -> GoodnessOfFit {
+    let mass = expected.mass();
+
+    let impossible = counts
+        .iter()
+        .any(|(value, count)| *count > 0.0 && expected.chance(*value) == 0.0);
+    if impossible {
+        return GoodnessOfFit {
+            statistic: f64::INFINITY,
+            degrees_of_freedom: expected.len().saturating_sub(1) as u32,
+            p_value: 0.0,
+        };
+    }
+
+    // the observed and expected number of rolls of each group of outcomes
+    let mut groups: Vec<(f64, f64)> = vec![];
+    let mut group = (0.0, 0.0);
+    for (value, chance) in expected.iter() {
+        group.0 += counts.get(&value).copied().unwrap_or(0.0);
+        group.1 += chance / mass * rolls;
+        if group.1 >= MIN_EXPECTED {
+            groups.push(group);
+            group = (0.0, 0.0);
+        }
+    }
+    match groups.last_mut() {
+        Some(last) => {
+            last.0 += group.0;
+            last.1 += group.1;
+        }
+        None => groups.push(group),
+    }
+
+    let statistic = groups
+        .iter()
+        .filter(|(_, expected)| *expected > 0.0)
+        .map(|(observed, expected)| (observed - expected).powi(2) / expected)
+        .sum();
+    let degrees_of_freedom = groups.len() as u32 - 1;
+
+    GoodnessOfFit {
+        statistic,
+        degrees_of_freedom,
+        p_value: chi_square_p_value(statistic, degrees_of_freedom),
+    }
+}
+
+impl Expression {
+    /// Roll the expression `rolls` times, and test whether the rolls are
+    /// consistent with its plot
+    pub fn verify_plot(&self, rolls: u64) -> GoodnessOfFit {
+        self.verify_plot_with(rolls, &mut seeded_rng(rand::thread_rng().gen()))
+    }
+
+    /// Roll the expression `rolls` times using the given random number
+    /// generator, and test whether the rolls are consistent with its plot
+    pub fn verify_plot_with<R: Rng + ?Sized>(&self, rolls: u64, rng: &mut R) -> GoodnessOfFit {
+        goodness_of_fit(&self.roll_many_with(rolls, rng), &self.plot().plot)
+    }
+
+    /// Test whether real rolls of the expression, such as a log of the
+    /// rolls of a die that might be cursed, are consistent with its plot
+    ///
+    /// the Kolmogorov-Smirnov test is conservative for dice, as their
+    /// outcomes are discrete, so its p-value is too high rather than too low
+    pub fn check_fairness(&self, rolls: &[RollResult]) -> FairnessReport {
+        let expected = self.plot().plot;
+        let mass = expected.mass();
+        let total = rolls.len() as f64;
+        // only the values that were rolled are counted, as a typo in a log
+        // can be arbitrarily far from the rest
+        let mut observed: BTreeMap<RollResult, f64> = BTreeMap::new();
+        for roll in rolls {
+            *observed.entry(*roll).or_insert(0.0) += 1.0;
+        }
+
+        let chi_square = fit_counts(&observed, total, &expected);
+
+        // every outcome that was either possible or rolled, in order. The
+        // distance between the cumulative chances only changes at these
+        let mut outcomes: BTreeMap<RollResult, (f64, Chance)> = expected
+            .iter()
+            .map(|(value, chance)| (value, (0.0, chance / mass)))
+            .collect();
+        for (value, hits) in &observed {
+            outcomes.entry(*value).or_insert((0.0, 0.0)).0 = *hits;
+        }
+
+        let mut distance: f64 = 0.0;
+        let (mut observed_so_far, mut expected_so_far) = (0.0, 0.0);
+        let mut deviations: Vec<Deviation> = vec![];
+        let mut impossible: Vec<(RollResult, u64)> = vec![];
+        for (value, (hits, chance)) in outcomes {
+            observed_so_far += hits;
+            expected_so_far += chance;
+            distance = distance.max((observed_so_far / total - expected_so_far).abs());
+
+            if chance == 0.0 {
+                if hits > 0.0 {
+                    impossible.push((value, hits as u64));
+                }
+                continue;
+            }
+            // nothing deviates without rolls
+            if total == 0.0 {
+                continue;
+            }
+            let expected = chance * total;
+            deviations.push(Deviation {
+                value,
+                observed: hits as u64,
+                expected,
+                residual: (hits - expected) / expected.sqrt(),
+            });
+        }
+        let by_residual = |a: &&Deviation, b: &&Deviation| a.residual.total_cmp(&b.residual);
+        let kolmogorov_smirnov = match rolls.len() {
+            0 => KolmogorovSmirnov {
+                statistic: 0.0,
+                p_value: 1.0,
+            },
+            _ => KolmogorovSmirnov {
+                statistic: distance,
+                p_value: kolmogorov_smirnov_p_value(distance, total),
+            },
+        };
+
+        FairnessReport {
+            rolls: rolls.len() as u64,
+            chi_square,
+            kolmogorov_smirnov,
+            most_over_represented: deviations.iter().max_by(by_residual).cloned(),
+            most_under_represented: deviations.iter().min_by(by_residual).cloned(),
+            impossible,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::Comparison;
+    use crate::expression::Query;
+    use crate::random::{below, DiceRng};
+    use crate::test_helpers::{assert_close, assert_within};
+    use crate::traits::RollResult;
+
+    use Expression::*;
+
+    #[test]
+    fn chi_square_matches_tables() {
+        // critical values at the 5% and 1% levels, to the precision of the
+        // tables
+        assert_within(0.05, chi_square_p_value(3.841_459, 1), 1e-6);
+        assert_within(0.05, chi_square_p_value(11.070_498, 5), 1e-6);
+        assert_within(0.01, chi_square_p_value(37.566_235, 20), 1e-6);
+        // the series and the continued fraction agree where they meet
+        assert_within(
+            upper_gamma(3.0, 3.999_999_999),
+            upper_gamma(3.0, 4.000_000_001),
+            1e-6,
+        );
+        assert_eq!(chi_square_p_value(0.0, 3), 1.0);
+        assert_eq!(chi_square_p_value(f64::INFINITY, 3), 0.0);
+    }
+
+    #[test]
+    fn mismatched_rolls_fail() {
+        let rolls = Die(6).roll_many_with(6000, &mut seeded_rng(1));
+
+        assert!(goodness_of_fit(&rolls, &Die(6).plot().plot).fits(0.001));
+        // a loaded die rolls more sixes
+        let loaded = Distribution::new(1, vec![0.15, 0.15, 0.15, 0.15, 0.15, 0.25]);
+        assert!(!goodness_of_fit(&rolls, &loaded).fits(0.001));
+        // a d6 can't roll a 7
+        let impossible = goodness_of_fit(&Die(7).plot(), &Die(6).plot().plot);
+        assert_eq!(impossible.p_value, 0.0);
+    }
+
+    #[test]
+    fn constants_always_fit() {
+        let actual = Constant(3).verify_plot(100);
+
+        assert_eq!(actual.degrees_of_freedom, 0);
+        assert_eq!(actual.p_value, 1.0);
+    }
+
+    /// Generates random expressions whose plots are small enough to verify
+    struct Generator {
+        rng: DiceRng,
+        variables: Vec<String>,
+    }
+
+    impl Generator {
+        fn pick(&mut self, bound: u32) -> u32 {
+            below(bound, &mut self.rng)
+        }
+
+        fn sides(&mut self) -> u32 {
+            [2, 3, 4, 6, 8][self.pick(5) as usize]
+        }
+
+        fn comparison(&mut self) -> Comparison {
+            match self.pick(5) {
+                0 => Comparison::GreaterThan,
+                1 => Comparison::GreaterThanOrEqualTo,
+                2 => Comparison::LessThan,
+                3 => Comparison::LessThanOrEqualTo,
+                _ => Comparison::EqualTo,
+            }
+        }
+
+        fn leaf(&mut self) -> Expression {
+            match self.pick(5) {
+                0 => Die(self.sides()),
+                1 => Constant(self.pick(7) as RollResult - 3),
+                2 => Dice {
+                    count: self.pick(3) + 1,
+                    sides: self.sides(),
+                },
+                3 => {
+                    let sides = self.sides();
+                    Count(
+                        self.comparison(),
+                        self.pick(sides) as RollResult + 1,
+                        self.pick(3) + 1,
+                        sides,
+                    )
+                }
+                _ if self.variables.is_empty() => Die(self.sides()),
+                _ => {
+                    let index = self.pick(self.variables.len() as u32) as usize;
+                    Variable(self.variables[index].clone())
+                }
+            }
+        }
+
+        /// an operand of an expression at the given depth
+        fn operand(&mut self, depth: u32) -> Box<Expression> {
+            Box::new(self.expression(depth - 1))
+        }
+
+        fn expression(&mut self, depth: u32) -> Expression {
+            if depth == 0 || self.pick(4) == 0 {
+                return self.leaf();
+            }
+
+            match self.pick(12) {
+                0 => Sum(self.operand(depth), self.operand(depth)),
+                1 => Diff(self.operand(depth), self.operand(depth)),
+                2 => Multiply(self.operand(depth), self.operand(depth)),
+                // never divide by zero
+                3 => Divide(self.operand(depth), Box::new(Die(self.sides()))),
+                4 => Advantage(self.operand(depth)),
+                5 => Disadvantage(self.operand(depth)),
+                6 => Compare(self.operand(depth), self.operand(depth), self.comparison()),
+                7 => {
+                    let options = (0..self.pick(2) + 2)
+                        .map(|_| (self.pick(3) + 1, self.expression(depth - 1)))
+                        .collect();
+                    Choice(options)
+                }
+                8 => {
+                    let name = format!("v{}", self.variables.len());
+                    let value = self.expression(depth - 1);
+                    self.variables.push(name.clone());
+                    let body = self.expression(depth - 1);
+                    self.variables.pop();
+                    Let(name, Box::new(value), Box::new(body))
+                }
+                9 => {
+                    // a condition that is met at least a sixth of the time
+                    let sides = self.sides();
+                    let face = self.pick(sides) as RollResult + 1;
+                    let condition = match self.pick(2) {
+                        0 => Compare(
+                            Box::new(Die(sides)),
+                            Box::new(Constant(face)),
+                            Comparison::GreaterThanOrEqualTo,
+                        ),
+                        _ => Compare(
+                            Box::new(Die(sides)),
+                            Box::new(Constant(face)),
+                            Comparison::LessThanOrEqualTo,
+                        ),
+                    };
+                    Given(self.operand(depth), Box::new(condition))
+                }
+                10 => Label(String::from("part"), self.operand(depth)),
+                _ => Query(Query::Expectation, self.operand(depth)),
+            }
+        }
+    }
+
+    #[test]
+    fn rolls_fit_plots_of_generated_expressions() {
+        let mut generator = Generator {
+            rng: seeded_rng(2024),
+            variables: vec![],
+        };
+        let mut rng = seeded_rng(1);
+
+        for _ in 0..100 {
+            let expression = generator.expression(3);
+
+            let actual = expression.verify_plot_with(4000, &mut rng);
+
+            // with fixed seeds, this only fails if `roll` and `plot` disagree
+            assert!(actual.fits(1e-4), "{}: {:?}", expression, actual);
+        }
+    }
+
+    #[test]
+    fn kolmogorov_smirnov_matches_tables() {
+        // the critical value at the 5% level is 1.358
+        assert!((kolmogorov_smirnov_p_value(1.358 / 1e4, 1e8) - 0.05).abs() < 1e-3);
+        assert_eq!(kolmogorov_smirnov_p_value(0.0, 100.0), 1.0);
+        assert!(kolmogorov_smirnov_p_value(0.5, 100.0) < 1e-9);
+    }
+
+    #[test]
+    fn fair_d20_passes() {
+        let mut rng = seeded_rng(20);
+        let rolls: Vec<RollResult> = (0..2000).map(|_| Die(20).roll_with(&mut rng)).collect();
+
+        let actual = Die(20).check_fairness(&rolls);
+
+        assert_eq!(actual.rolls, 2000);
+        assert_eq!(actual.chi_square.degrees_of_freedom, 19);
+        assert!(actual.chi_square.fits(0.01), "{:?}", actual);
+        assert!(actual.kolmogorov_smirnov.p_value > 0.01, "{:?}", actual);
+    }
+
+    #[test]
+    fn cursed_d20_fails() {
+        // a d20 that rolls a 1 instead of a 20 half of the time
+        let mut rng = seeded_rng(13);
+        let rolls: Vec<RollResult> = (0..2000)
+            .map(|index| match Die(20).roll_with(&mut rng) {
+                20 if index % 2 == 0 => 1,
+                roll => roll,
+            })
+            .collect();
+
+        let actual = Die(20).check_fairness(&rolls);
+
+        assert!(actual.chi_square.p_value < 1e-6, "{:?}", actual);
+        assert!(actual.kolmogorov_smirnov.p_value < 0.01, "{:?}", actual);
+        let over = actual.most_over_represented.unwrap();
+        assert_eq!(over.value, 1);
+        assert!(over.observed as f64 > over.expected);
+        assert_eq!(actual.most_under_represented.unwrap().value, 20);
+    }
+
+    #[test]
+    fn impossible_rolls_are_reported() {
+        let actual = Die(6).check_fairness(&[1, 2, 3, 7]);
+
+        assert_eq!(actual.chi_square.p_value, 0.0);
+        assert_eq!(actual.impossible, vec![(7, 1)]);
+        assert!(actual.most_over_represented.unwrap().value <= 3);
+        assert_eq!(actual.most_under_represented.unwrap().observed, 0);
+
+        // a typo in the log is only reported, however far off it is
+        let typo = Die(20).check_fairness(&[1, 2_000_000_000]);
+        assert_eq!(typo.impossible, vec![(2_000_000_000, 1)]);
+        assert_close(0.5, typo.kolmogorov_smirnov.statistic);
+
+        let empty = Die(6).check_fairness(&[]);
+        assert_eq!(empty.kolmogorov_smirnov.p_value, 1.0);
+        assert_eq!(empty.most_over_represented, None);
+        assert!(empty.chi_square.fits(0.01));
+    }
+}
@@ -0,0 +1,268 @@
+//! Weight Module
+//!
+//! Representations for the likelihood of an outcome. Plots can be computed
+//! with single or double precision floats, in log-space for outcomes too
+//! unlikely to be represented as a float, or exactly as rational numbers.
+
+use num_bigint::BigUint;
+use num_rational::Ratio;
+use num_traits::{One, ToPrimitive, Zero};
+use std::fmt;
+use std::fmt::Debug;
+
+/// The likelihood of an outcome
+pub trait Weight: Clone + Debug + PartialEq {
+    /// the likelihood of an impossible outcome
+    fn zero() -> Self;
+
+    /// the likelihood of a certain outcome
+    fn one() -> Self;
+
+    /// the likelihood of `numerator` out of `denominator` equally likely
+    /// outcomes
+    fn ratio(numerator: u64, denominator: u64) -> Self;
+
+    /// the likelihood of either of two exclusive outcomes
+    fn plus(&self, other: &Self) -> Self;
+
+    /// the likelihood of both of two independent outcomes
+    fn times(&self, other: &Self) -> Self;
+
+    /// the likelihood of this outcome, given that an outcome which includes
+    /// it, with likelihood `other`, has happened
+    fn divide(&self, other: &Self) -> Self;
+
+    /// whether this is the likelihood of an impossible outcome
+    fn is_zero(&self) -> bool;
+
+    /// convert this likelihood into a probability between 0 and 1
+    fn to_f64(&self) -> f64;
+}
+
+impl Weight for f32 {
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn one() -> Self {
+        1.0
+    }
+
+    fn ratio(numerator: u64, denominator: u64) -> Self {
+        numerator as f32 / denominator as f32
+    }
+
+    fn plus(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn times(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn divide(&self, other: &Self) -> Self {
+        self / other
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0.0
+    }
+
+    fn to_f64(&self) -> f64 {
+        f64::from(*self)
+    }
+}
+
+impl Weight for f64 {
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn one() -> Self {
+        1.0
+    }
+
+    fn ratio(numerator: u64, denominator: u64) -> Self {
+        numerator as f64 / denominator as f64
+    }
+
+    fn plus(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn times(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn divide(&self, other: &Self) -> Self {
+        self / other
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0.0
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+}
+
+/// A likelihood stored as its natural logarithm, for outcomes too unlikely to
+/// be represented by a float, such as rolling 1000 on 1000d6
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogChance(pub f64);
+
+impl LogChance {
+    /// the natural logarithm of the likelihood
+    pub fn ln(&self) -> f64 {
+        self.0
+    }
+}
+
+impl Weight for LogChance {
+    fn zero() -> Self {
+        LogChance(f64::NEG_INFINITY)
+    }
+
+    fn one() -> Self {
+        LogChance(0.0)
+    }
+
+    fn ratio(numerator: u64, denominator: u64) -> Self {
+        LogChance((numerator as f64).ln() - (denominator as f64).ln())
+    }
+
+    fn plus(&self, other: &Self) -> Self {
+        let (high, low) = if self.0 > other.0 {
+            (self.0, other.0)
+        } else {
+            (other.0, self.0)
+        };
+        if low == f64::NEG_INFINITY {
+            return LogChance(high);
+        }
+
+        LogChance(high + (low - high).exp().ln_1p())
+    }
+
+    fn times(&self, other: &Self) -> Self {
+        LogChance(self.0 + other.0)
+    }
+
+    fn divide(&self, other: &Self) -> Self {
+        LogChance(self.0 - other.0)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0 == f64::NEG_INFINITY
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.0.exp()
+    }
+}
+
+/// An exact likelihood, as a ratio of arbitrarily large integers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exact(pub Ratio<BigUint>);
+
+impl Exact {
+    /// the number of outcomes that are counted
+    pub fn numerator(&self) -> &BigUint {
+        self.0.numer()
+    }
+
+    /// the number of outcomes that are possible
+    pub fn denominator(&self) -> &BigUint {
+        self.0.denom()
+    }
+}
+
+impl fmt::Display for Exact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator(), self.denominator())
+    }
+}
+
+impl Weight for Exact {
+    fn zero() -> Self {
+        Exact(Ratio::zero())
+    }
+
+    fn one() -> Self {
+        Exact(Ratio::one())
+    }
+
+    fn ratio(numerator: u64, denominator: u64) -> Self {
+        Exact(Ratio::new(
+            BigUint::from(numerator),
+            BigUint::from(denominator),
+        ))
+    }
+
+    fn plus(&self, other: &Self) -> Self {
+        Exact(&self.0 + &other.0)
+    }
+
+    fn times(&self, other: &Self) -> Self {
+        Exact(&self.0 * &other.0)
+    }
+
+    fn divide(&self, other: &Self) -> Self {
+        Exact(&self.0 / &other.0)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    fn to_f64(&self) -> f64 {
+        // scale both sides down so that huge counts still convert
+        let shift = self.denominator().bits().saturating_sub(1000);
+        let numerator = (self.numerator() >> shift)
+            .to_f64()
+            .unwrap_or(f64::INFINITY);
+        let denominator = (self.denominator() >> shift)
+            .to_f64()
+            .unwrap_or(f64::INFINITY);
+
+        numerator / denominator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_chance_matches_chance() {
+        let half = LogChance::ratio(1, 2);
+        let quarter = LogChance::ratio(1, 4);
+
+        assert!((half.plus(&quarter).to_f64() - 0.75).abs() < 1e-12);
+        assert!((half.times(&quarter).to_f64() - 0.125).abs() < 1e-12);
+        assert!((quarter.divide(&half).to_f64() - 0.5).abs() < 1e-12);
+        assert_eq!(half.plus(&LogChance::zero()), half);
+        assert!(LogChance::zero().plus(&LogChance::zero()).is_zero());
+    }
+
+    #[test]
+    fn exact_is_exact() {
+        let third = Exact::ratio(1, 3);
+        let sixth = Exact::ratio(1, 6);
+
+        assert_eq!(third.plus(&sixth), Exact::ratio(1, 2));
+        assert_eq!(third.times(&sixth), Exact::ratio(1, 18));
+        assert_eq!(sixth.divide(&third), Exact::ratio(1, 2));
+        assert_eq!(third.to_f64(), 1.0 / 3.0);
+        assert_eq!(third.to_string(), "1/3");
+    }
+
+    #[test]
+    fn exact_converts_huge_ratios() {
+        let tiny = (0..500).fold(Exact::one(), |acc, _| acc.times(&Exact::ratio(1, 6)));
+
+        assert_eq!(tiny.to_f64(), 0.0);
+        assert!((tiny.plus(&Exact::ratio(1, 2)).to_f64() - 0.5).abs() < 1e-12);
+    }
+}